@@ -27,11 +27,36 @@ CREATE TABLE public._sqlx_migrations (
 
 
 
+CREATE TABLE public.access_keys (
+    id integer NOT NULL,
+    access_key_id character varying(128) NOT NULL,
+    secret_access_key character varying(128) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+
+CREATE SEQUENCE public.access_keys_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+
+ALTER SEQUENCE public.access_keys_id_seq OWNED BY public.access_keys.id;
+
+
+
 CREATE TABLE public.buckets (
     id integer NOT NULL,
     name character varying(63) NOT NULL,
     created_at timestamp with time zone DEFAULT now() NOT NULL,
-    CONSTRAINT buckets_name_check CHECK (((char_length((name)::text) >= 3) AND (char_length((name)::text) <= 63)))
+    versioning character varying(16) DEFAULT 'Unversioned'::character varying NOT NULL,
+    CONSTRAINT buckets_name_check CHECK (((char_length((name)::text) >= 3) AND (char_length((name)::text) <= 63))),
+    CONSTRAINT buckets_versioning_check CHECK (((versioning)::text = ANY ((ARRAY['Unversioned'::character varying, 'Enabled'::character varying, 'Suspended'::character varying])::text[])))
 );
 
 
@@ -56,7 +81,11 @@ CREATE TABLE public.file_data (
     md5 bytea NOT NULL,
     sha1 bytea,
     sha256 bytea,
-    created_at timestamp with time zone DEFAULT now() NOT NULL
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    multipart_parts_count integer,
+    crc32 bytea,
+    crc32c bytea,
+    checksum_algorithm text
 );
 
 
@@ -103,12 +132,16 @@ ALTER SEQUENCE public.file_data_part_chunk_info_id_seq OWNED BY public.file_data
 
 CREATE TABLE public.file_data_parts (
     id integer NOT NULL,
-    file_data_id integer NOT NULL,
+    file_data_id integer,
     backend_key character varying(1024) NOT NULL,
     range int8range NOT NULL,
     encrypt_metadata jsonb,
     encrypt_bindata bytea,
-    created_at timestamp with time zone DEFAULT now() NOT NULL
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    multipart_upload_part_id integer,
+    backend_offset bigint DEFAULT 0 NOT NULL,
+    driver text NOT NULL,
+    CONSTRAINT file_data_parts_check CHECK (((file_data_id IS NOT NULL) OR (multipart_upload_part_id IS NOT NULL)))
 );
 
 
@@ -133,7 +166,14 @@ CREATE TABLE public.file_versions (
     file_data_id integer,
     is_delete_marker boolean DEFAULT false NOT NULL,
     user_metadata jsonb,
-    created_at timestamp with time zone DEFAULT now() NOT NULL
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    is_null_version boolean DEFAULT false NOT NULL,
+    content_type text,
+    content_encoding text,
+    content_disposition text,
+    content_language text,
+    cache_control text,
+    expires text
 );
 
 
@@ -155,7 +195,7 @@ ALTER SEQUENCE public.file_versions_id_seq OWNED BY public.file_versions.id;
 CREATE TABLE public.files (
     id integer NOT NULL,
     bucket_id integer NOT NULL,
-    key character varying(1024) NOT NULL,
+    key character varying(1024) NOT NULL COLLATE pg_catalog."C",
     current_version integer NOT NULL,
     current_version_is_delete_marker boolean DEFAULT false NOT NULL,
     created_at timestamp with time zone DEFAULT now() NOT NULL,
@@ -178,6 +218,67 @@ ALTER SEQUENCE public.files_id_seq OWNED BY public.files.id;
 
 
 
+CREATE TABLE public.multipart_upload_parts (
+    id integer NOT NULL,
+    multipart_upload_id integer NOT NULL,
+    part_number integer NOT NULL,
+    size bigint NOT NULL,
+    md5 bytea NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT multipart_upload_parts_part_number_check CHECK (((part_number >= 1) AND (part_number <= 10000)))
+);
+
+
+
+CREATE SEQUENCE public.multipart_upload_parts_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+
+ALTER SEQUENCE public.multipart_upload_parts_id_seq OWNED BY public.multipart_upload_parts.id;
+
+
+
+CREATE TABLE public.multipart_uploads (
+    id integer NOT NULL,
+    upload_id character varying(64) DEFAULT replace((gen_random_uuid())::text, '-'::text, ''::text) NOT NULL,
+    bucket_id integer NOT NULL,
+    key character varying(1024) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    content_type text,
+    content_encoding text,
+    content_disposition text,
+    content_language text,
+    cache_control text,
+    expires text,
+    user_metadata jsonb
+);
+
+
+
+CREATE SEQUENCE public.multipart_uploads_id_seq
+    AS integer
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+
+ALTER SEQUENCE public.multipart_uploads_id_seq OWNED BY public.multipart_uploads.id;
+
+
+
+ALTER TABLE ONLY public.access_keys ALTER COLUMN id SET DEFAULT nextval('public.access_keys_id_seq'::regclass);
+
+
+
 ALTER TABLE ONLY public.buckets ALTER COLUMN id SET DEFAULT nextval('public.buckets_id_seq'::regclass);
 
 
@@ -202,11 +303,29 @@ ALTER TABLE ONLY public.files ALTER COLUMN id SET DEFAULT nextval('public.files_
 
 
 
+ALTER TABLE ONLY public.multipart_upload_parts ALTER COLUMN id SET DEFAULT nextval('public.multipart_upload_parts_id_seq'::regclass);
+
+
+
+ALTER TABLE ONLY public.multipart_uploads ALTER COLUMN id SET DEFAULT nextval('public.multipart_uploads_id_seq'::regclass);
+
+
+
 ALTER TABLE ONLY public._sqlx_migrations
     ADD CONSTRAINT _sqlx_migrations_pkey PRIMARY KEY (version);
 
 
 
+ALTER TABLE ONLY public.access_keys
+    ADD CONSTRAINT access_keys_access_key_id_key UNIQUE (access_key_id);
+
+
+
+ALTER TABLE ONLY public.access_keys
+    ADD CONSTRAINT access_keys_pkey PRIMARY KEY (id);
+
+
+
 ALTER TABLE ONLY public.buckets
     ADD CONSTRAINT buckets_name_key UNIQUE (name);
 
@@ -257,6 +376,30 @@ ALTER TABLE ONLY public.files
 
 
 
+ALTER TABLE ONLY public.multipart_upload_parts
+    ADD CONSTRAINT multipart_upload_parts_multipart_upload_id_part_number_key UNIQUE (multipart_upload_id, part_number);
+
+
+
+ALTER TABLE ONLY public.multipart_upload_parts
+    ADD CONSTRAINT multipart_upload_parts_pkey PRIMARY KEY (id);
+
+
+
+ALTER TABLE ONLY public.multipart_uploads
+    ADD CONSTRAINT multipart_uploads_pkey PRIMARY KEY (id);
+
+
+
+ALTER TABLE ONLY public.multipart_uploads
+    ADD CONSTRAINT multipart_uploads_upload_id_key UNIQUE (upload_id);
+
+
+
+CREATE UNIQUE INDEX file_versions_file_id_null_version_key ON public.file_versions USING btree (file_id) WHERE is_null_version;
+
+
+
 ALTER TABLE ONLY public.file_data_part_chunk_info
     ADD CONSTRAINT file_data_part_chunk_info_part_id_fkey FOREIGN KEY (part_id) REFERENCES public.file_data_parts(id) ON DELETE RESTRICT;
 
@@ -267,6 +410,11 @@ ALTER TABLE ONLY public.file_data_parts
 
 
 
+ALTER TABLE ONLY public.file_data_parts
+    ADD CONSTRAINT file_data_parts_multipart_upload_part_id_fkey FOREIGN KEY (multipart_upload_part_id) REFERENCES public.multipart_upload_parts(id) ON DELETE RESTRICT;
+
+
+
 ALTER TABLE ONLY public.file_versions
     ADD CONSTRAINT file_versions_file_data_id_fkey FOREIGN KEY (file_data_id) REFERENCES public.file_data(id) ON DELETE RESTRICT;
 
@@ -283,12 +431,22 @@ ALTER TABLE ONLY public.files
 
 
 ALTER TABLE ONLY public.files
-    ADD CONSTRAINT files_current_version_current_version_is_delete_marker_fkey FOREIGN KEY (current_version, current_version_is_delete_marker) REFERENCES public.file_versions(id, is_delete_marker) ON DELETE RESTRICT DEFERRABLE;
+    ADD CONSTRAINT files_current_version_current_version_is_delete_marker_fkey FOREIGN KEY (current_version, current_version_is_delete_marker) REFERENCES public.file_versions(id, is_delete_marker) DEFERRABLE;
 
 
 
 ALTER TABLE ONLY public.files
-    ADD CONSTRAINT files_current_version_fkey FOREIGN KEY (current_version) REFERENCES public.file_versions(id) ON DELETE RESTRICT DEFERRABLE;
+    ADD CONSTRAINT files_current_version_fkey FOREIGN KEY (current_version) REFERENCES public.file_versions(id) DEFERRABLE;
+
+
+
+ALTER TABLE ONLY public.multipart_upload_parts
+    ADD CONSTRAINT multipart_upload_parts_multipart_upload_id_fkey FOREIGN KEY (multipart_upload_id) REFERENCES public.multipart_uploads(id) ON DELETE RESTRICT;
+
+
+
+ALTER TABLE ONLY public.multipart_uploads
+    ADD CONSTRAINT multipart_uploads_bucket_id_fkey FOREIGN KEY (bucket_id) REFERENCES public.buckets(id) ON DELETE RESTRICT;
 
 
 
@@ -0,0 +1,9 @@
+ALTER TABLE file_data DROP COLUMN multipart_parts_count;
+
+DELETE FROM file_data_part_chunk_info WHERE part_id IN (SELECT id FROM file_data_parts WHERE file_data_id IS NULL);
+DELETE FROM file_data_parts WHERE file_data_id IS NULL;
+ALTER TABLE file_data_parts DROP COLUMN multipart_upload_part_id;
+ALTER TABLE file_data_parts ALTER COLUMN file_data_id SET NOT NULL;
+
+DROP TABLE IF EXISTS multipart_upload_parts;
+DROP TABLE IF EXISTS multipart_uploads;
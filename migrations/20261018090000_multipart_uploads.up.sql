@@ -0,0 +1,25 @@
+CREATE TABLE multipart_uploads (
+    id SERIAL PRIMARY KEY,
+    upload_id VARCHAR(64) NOT NULL UNIQUE DEFAULT replace(gen_random_uuid()::text, '-', ''),
+    bucket_id INTEGER NOT NULL REFERENCES buckets(id) ON DELETE RESTRICT,
+    key VARCHAR(1024) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE multipart_upload_parts (
+    id SERIAL PRIMARY KEY,
+    multipart_upload_id INTEGER NOT NULL REFERENCES multipart_uploads(id) ON DELETE RESTRICT,
+    part_number INTEGER NOT NULL CHECK (part_number BETWEEN 1 AND 10000),
+    size BIGINT NOT NULL,
+    md5 BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE(multipart_upload_id, part_number)
+);
+
+-- parts of an in-progress multipart upload are not attached to a file_data yet
+ALTER TABLE file_data_parts ALTER COLUMN file_data_id DROP NOT NULL;
+ALTER TABLE file_data_parts ADD COLUMN multipart_upload_part_id INTEGER REFERENCES multipart_upload_parts(id) ON DELETE RESTRICT;
+ALTER TABLE file_data_parts ADD CHECK (file_data_id IS NOT NULL OR multipart_upload_part_id IS NOT NULL);
+
+-- number of parts the object was assembled from, for the "md5-N" style ETag
+ALTER TABLE file_data ADD COLUMN multipart_parts_count INTEGER;
@@ -0,0 +1,8 @@
+ALTER TABLE files DROP CONSTRAINT files_current_version_fkey;
+ALTER TABLE files DROP CONSTRAINT files_current_version_current_version_is_delete_marker_fkey;
+ALTER TABLE files ADD FOREIGN KEY (current_version) REFERENCES file_versions(id) ON DELETE RESTRICT DEFERRABLE;
+ALTER TABLE files ADD FOREIGN KEY (current_version, current_version_is_delete_marker) REFERENCES file_versions(id, is_delete_marker) ON DELETE RESTRICT DEFERRABLE;
+
+DROP INDEX IF EXISTS file_versions_file_id_null_version_key;
+ALTER TABLE file_versions DROP COLUMN is_null_version;
+ALTER TABLE buckets DROP COLUMN versioning;
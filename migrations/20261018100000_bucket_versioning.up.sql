@@ -0,0 +1,15 @@
+ALTER TABLE buckets ADD COLUMN versioning VARCHAR(16) NOT NULL DEFAULT 'Unversioned' CHECK (versioning IN ('Unversioned', 'Enabled', 'Suspended'));
+-- buckets created before this migration kept every version of their objects
+UPDATE buckets SET versioning = 'Enabled';
+
+-- versions created while versioning is not enabled have the "null" version id,
+-- and there is at most one of them per file
+ALTER TABLE file_versions ADD COLUMN is_null_version BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE UNIQUE INDEX file_versions_file_id_null_version_key ON file_versions(file_id) WHERE is_null_version;
+
+-- RESTRICT is checked immediately even for deferrable constraints, which makes it
+-- impossible to replace or remove the current version of a file
+ALTER TABLE files DROP CONSTRAINT files_current_version_fkey;
+ALTER TABLE files DROP CONSTRAINT files_current_version_current_version_is_delete_marker_fkey;
+ALTER TABLE files ADD FOREIGN KEY (current_version) REFERENCES file_versions(id) DEFERRABLE;
+ALTER TABLE files ADD FOREIGN KEY (current_version, current_version_is_delete_marker) REFERENCES file_versions(id, is_delete_marker) DEFERRABLE;
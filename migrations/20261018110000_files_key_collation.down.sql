@@ -0,0 +1 @@
+ALTER TABLE files ALTER COLUMN key TYPE VARCHAR(1024) COLLATE "default";
@@ -0,0 +1,2 @@
+-- S3 lists keys in UTF-8 binary order, which is what the "C" collation compares by
+ALTER TABLE files ALTER COLUMN key TYPE VARCHAR(1024) COLLATE "C";
@@ -0,0 +1,15 @@
+ALTER TABLE multipart_uploads
+    DROP COLUMN content_type,
+    DROP COLUMN content_encoding,
+    DROP COLUMN content_disposition,
+    DROP COLUMN content_language,
+    DROP COLUMN cache_control,
+    DROP COLUMN expires;
+
+ALTER TABLE file_versions
+    DROP COLUMN content_type,
+    DROP COLUMN content_encoding,
+    DROP COLUMN content_disposition,
+    DROP COLUMN content_language,
+    DROP COLUMN cache_control,
+    DROP COLUMN expires;
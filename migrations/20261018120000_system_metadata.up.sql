@@ -0,0 +1,16 @@
+-- system metadata given on PUT (or CreateMultipartUpload), returned as-is on GET/HEAD
+ALTER TABLE file_versions
+    ADD COLUMN content_type TEXT,
+    ADD COLUMN content_encoding TEXT,
+    ADD COLUMN content_disposition TEXT,
+    ADD COLUMN content_language TEXT,
+    ADD COLUMN cache_control TEXT,
+    ADD COLUMN expires TEXT;
+
+ALTER TABLE multipart_uploads
+    ADD COLUMN content_type TEXT,
+    ADD COLUMN content_encoding TEXT,
+    ADD COLUMN content_disposition TEXT,
+    ADD COLUMN content_language TEXT,
+    ADD COLUMN cache_control TEXT,
+    ADD COLUMN expires TEXT;
@@ -0,0 +1,2 @@
+-- x-amz-meta-* headers given on CreateMultipartUpload, moved to file_versions.user_metadata on completion
+ALTER TABLE multipart_uploads ADD COLUMN user_metadata JSONB;
@@ -0,0 +1,8 @@
+-- credentials for SigV4. the secret has to be stored as-is, since verifying a
+-- signature means computing it again
+CREATE TABLE access_keys (
+    id SERIAL PRIMARY KEY,
+    access_key_id VARCHAR(128) NOT NULL UNIQUE,
+    secret_access_key VARCHAR(128) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
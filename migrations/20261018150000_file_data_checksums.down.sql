@@ -0,0 +1,4 @@
+ALTER TABLE file_data
+    DROP COLUMN crc32,
+    DROP COLUMN crc32c,
+    DROP COLUMN checksum_algorithm;
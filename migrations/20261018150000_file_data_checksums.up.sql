@@ -0,0 +1,5 @@
+-- whole-object checksums besides the existing sha1/sha256, and the one the client asked for
+ALTER TABLE file_data
+    ADD COLUMN crc32 BYTEA,
+    ADD COLUMN crc32c BYTEA,
+    ADD COLUMN checksum_algorithm TEXT;
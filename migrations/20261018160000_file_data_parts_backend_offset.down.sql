@@ -0,0 +1 @@
+ALTER TABLE file_data_parts DROP COLUMN backend_offset;
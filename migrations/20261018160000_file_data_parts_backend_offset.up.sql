@@ -0,0 +1,3 @@
+-- parts created by UploadPartCopy reference a sub-range of an existing backend object:
+-- byte lower(range) of the object is at backend_offset in the backend object
+ALTER TABLE file_data_parts ADD COLUMN backend_offset BIGINT NOT NULL DEFAULT 0;
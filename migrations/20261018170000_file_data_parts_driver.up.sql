@@ -0,0 +1,3 @@
+-- the storage driver owning backend_key; everything before this was stored in ton
+ALTER TABLE file_data_parts ADD COLUMN driver TEXT NOT NULL DEFAULT 'ton';
+ALTER TABLE file_data_parts ALTER COLUMN driver DROP DEFAULT;
@@ -0,0 +1,99 @@
+use axum::body::{BodyDataStream, Bytes};
+use md5::Digest;
+use sha2::Sha256;
+use tokio_stream::StreamExt;
+
+use super::{ChunkInfo, UploadResult};
+use crate::s3serv::{checksum::ChecksumsHasher, error::S3Error};
+
+/// Splits a request body into chunks, hashing every chunk and the whole body on the way.
+pub struct Chunker<'a> {
+    body: &'a mut BodyDataStream,
+    buf: Vec<u8>,
+    pending: Bytes,
+    ended: bool,
+    offset: u64,
+    md5: md5::Md5,
+    checksums: ChecksumsHasher,
+    chunks: Vec<ChunkInfo>,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn new(body: &'a mut BodyDataStream) -> Self {
+        Chunker {
+            body,
+            buf: Vec::new(),
+            pending: Bytes::new(),
+            ended: false,
+            offset: 0,
+            md5: md5::Md5::new(),
+            checksums: ChecksumsHasher::default(),
+            chunks: Vec::new(),
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<(), S3Error> {
+        match self.body.next().await {
+            None => self.ended = true,
+            Some(Ok(v)) => self.pending = v,
+            Some(Err(e)) => {
+                tracing::error!("Failed to read frame: {:?}", e);
+                return Err(S3Error::from_body_error(e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether nothing is left to read, waiting for the next non-empty frame if needed.
+    pub async fn is_empty(&mut self) -> Result<bool, S3Error> {
+        while self.pending.is_empty() && !self.ended {
+            self.read_frame().await?;
+        }
+        Ok(self.pending.is_empty())
+    }
+
+    /// Reads the next chunk of `chunk_size` bytes, or less at the end of the body, and its
+    /// offset in the body. Gives `None` once the body is exhausted.
+    pub async fn next_chunk(&mut self, chunk_size: usize) -> Result<Option<(u64, &[u8])>, S3Error> {
+        assert!(chunk_size > 0);
+        self.buf.clear();
+        while self.buf.len() < chunk_size {
+            if !self.pending.is_empty() {
+                let n = (chunk_size - self.buf.len()).min(self.pending.len());
+                self.buf.extend_from_slice(&self.pending.split_to(n));
+            } else if self.ended {
+                break;
+            } else {
+                self.read_frame().await?;
+            }
+        }
+
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        self.md5.update(&self.buf);
+        self.checksums.update(&self.buf);
+        let end = self.offset + self.buf.len() as u64;
+        self.chunks.push(ChunkInfo {
+            range: self.offset as i64..end as i64,
+            md5: md5::Md5::digest(&self.buf).into(),
+            sha256: Sha256::digest(&self.buf).into(),
+        });
+        let offset = self.offset;
+        self.offset = end;
+        Ok(Some((offset, &self.buf)))
+    }
+
+    /// The digests of everything read so far. `r#ref` is left empty for the driver to
+    /// fill in once the object is committed.
+    pub fn finish(self) -> UploadResult {
+        UploadResult {
+            r#ref: String::new(),
+            md5: self.md5.finalize().into(),
+            size: self.offset,
+            checksums: self.checksums.finalize(),
+            chunks: self.chunks,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    body::{BodyDataStream, Bytes},
+    response::{IntoResponse, Response},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{chunker::Chunker, ByteStream, StorageDriver, UploadResult, UploadVerifier};
+use crate::s3serv::error::S3Error;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Stores every object as a single file under a local directory.
+///
+/// Uploads are written to `tmp/` and renamed into `objects/` once verified, so a
+/// crash never leaves a partial object behind a key.
+pub struct FsDriver {
+    root: PathBuf,
+}
+
+impl FsDriver {
+    /// Clears what uploads interrupted by a crash left in `tmp/`, so the directory must
+    /// not be shared with another running instance.
+    pub fn new(root: PathBuf) -> Self {
+        let driver = FsDriver { root };
+        let tmp_dir = driver.tmp_dir();
+        match std::fs::remove_dir_all(&tmp_dir) {
+            Ok(()) => tracing::info!("Cleared {:?}", tmp_dir),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => tracing::error!("Failed to clear {:?}: {:?}", tmp_dir, e),
+        }
+        driver
+    }
+
+    fn tmp_dir(&self) -> PathBuf {
+        self.root.join("tmp")
+    }
+
+    fn object_path(&self, backend_key: &str) -> PathBuf {
+        // spread the files over 256 directories
+        self.root
+            .join("objects")
+            .join(&backend_key[..2])
+            .join(backend_key)
+    }
+
+    async fn write_chunks(&self, chunker: &mut Chunker<'_>, path: &Path) -> Result<(), Response> {
+        let mut file = match tokio::fs::File::create(path).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to create {:?}: {:?}", path, e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        };
+
+        while let Some((_, chunk)) = chunker
+            .next_chunk(CHUNK_SIZE)
+            .await
+            .map_err(|e| e.into_response())?
+        {
+            if let Err(e) = file.write_all(chunk).await {
+                tracing::error!("Failed to write {:?}: {:?}", path, e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        }
+
+        if let Err(e) = file.sync_all().await {
+            tracing::error!("Failed to sync {:?}: {:?}", path, e);
+            return Err(S3Error::InternalError.into_response());
+        }
+        Ok(())
+    }
+
+    /// Moves a written upload to its final place.
+    async fn commit(&self, tmp_path: &Path, backend_key: &str) -> Result<(), Response> {
+        let path = self.object_path(backend_key);
+        let dir = path.parent().unwrap();
+        if let Err(e) = tokio::fs::create_dir_all(dir).await {
+            tracing::error!("Failed to create {:?}: {:?}", dir, e);
+            return Err(S3Error::InternalError.into_response());
+        }
+        if let Err(e) = tokio::fs::rename(tmp_path, &path).await {
+            tracing::error!("Failed to rename {:?}: {:?}", tmp_path, e);
+            return Err(S3Error::InternalError.into_response());
+        }
+        // make the rename itself durable
+        let synced = match tokio::fs::File::open(dir).await {
+            Ok(v) => v.sync_all().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = synced {
+            tracing::error!("Failed to sync {:?}: {:?}", dir, e);
+            return Err(S3Error::InternalError.into_response());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageDriver for FsDriver {
+    fn name(&self) -> &'static str {
+        "fs"
+    }
+
+    async fn upload(
+        &self,
+        body: &mut BodyDataStream,
+        verify: UploadVerifier<'_>,
+    ) -> Result<Option<UploadResult>, Response> {
+        let mut chunker = Chunker::new(body);
+        if chunker.is_empty().await.map_err(|e| e.into_response())? {
+            let result = chunker.finish();
+            return match verify(&result.md5, &result.checksums) {
+                Ok(()) => Ok(None),
+                Err(e) => Err(e.into_response()),
+            };
+        }
+
+        let tmp_dir = self.tmp_dir();
+        if let Err(e) = tokio::fs::create_dir_all(&tmp_dir).await {
+            tracing::error!("Failed to create {:?}: {:?}", tmp_dir, e);
+            return Err(S3Error::InternalError.into_response());
+        }
+
+        let backend_key = uuid::Uuid::new_v4().simple().to_string();
+        let tmp_path = tmp_dir.join(&backend_key);
+
+        let written = async {
+            self.write_chunks(&mut chunker, &tmp_path).await?;
+            let result = chunker.finish();
+            if let Err(e) = verify(&result.md5, &result.checksums) {
+                tracing::info!("Abandoning upload: {}", e);
+                return Err(e.into_response());
+            }
+            self.commit(&tmp_path, &backend_key).await?;
+            Ok(result)
+        }
+        .await;
+
+        match written {
+            Ok(mut result) => {
+                result.r#ref = backend_key;
+                Ok(Some(result))
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    fn read_range(&self, backend_key: &str, range: Range<i64>, _chunk_start: i64) -> ByteStream {
+        let path = self.object_path(backend_key);
+        let stream = async_stream::stream! {
+            let mut file = match tokio::fs::File::open(&path).await {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Failed to open {:?}: {:?}", path, e);
+                    yield Err(e);
+                    return;
+                }
+            };
+            if let Err(e) = file.seek(SeekFrom::Start(range.start as u64)).await {
+                tracing::error!("Failed to seek {:?}: {:?}", path, e);
+                yield Err(e);
+                return;
+            }
+
+            let mut remaining = (range.end - range.start) as usize;
+            while remaining > 0 {
+                let mut buf = vec![0; remaining.min(READ_BUFFER_SIZE)];
+                let n = match file.read(&mut buf).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::error!("Failed to read {:?}: {:?}", path, e);
+                        yield Err(e);
+                        return;
+                    }
+                };
+                if n == 0 {
+                    tracing::error!("{:?} is shorter than expected", path);
+                    yield Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+                    return;
+                }
+                buf.truncate(n);
+                remaining -= n;
+                yield Ok::<Bytes, std::io::Error>(Bytes::from(buf));
+            }
+        };
+        Box::pin(stream)
+    }
+
+    async fn delete(&self, backend_key: &str) -> Result<(), S3Error> {
+        let path = self.object_path(backend_key);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to delete {:?}: {:?}", path, e);
+                Err(S3Error::InternalError)
+            }
+        }
+    }
+
+    async fn health(&self) -> bool {
+        // uploads need it anyway, and it fails when the directory is not writable
+        match tokio::fs::create_dir_all(self.tmp_dir()).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::debug!("{:?} is not writable: {:?}", self.root, e);
+                false
+            }
+        }
+    }
+}
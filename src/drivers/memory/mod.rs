@@ -0,0 +1,89 @@
+use std::{collections::HashMap, ops::Range, sync::Mutex};
+
+use axum::{
+    body::{BodyDataStream, Bytes},
+    response::{IntoResponse, Response},
+};
+
+use super::{chunker::Chunker, ByteStream, StorageDriver, UploadResult, UploadVerifier};
+use crate::s3serv::error::S3Error;
+
+/// Keeps objects in memory, for tests. Everything is lost on restart while the metadata
+/// in Postgres is not, so it is not offered by [`super::Drivers::from_env`].
+pub struct MemoryDriver {
+    chunk_size: usize,
+    objects: Mutex<HashMap<String, Bytes>>,
+}
+
+impl MemoryDriver {
+    /// `chunk_size` only decides how `file_data_part_chunk_info` is split.
+    pub fn new(chunk_size: usize) -> Self {
+        MemoryDriver {
+            chunk_size,
+            objects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageDriver for MemoryDriver {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn upload(
+        &self,
+        body: &mut BodyDataStream,
+        verify: UploadVerifier<'_>,
+    ) -> Result<Option<UploadResult>, Response> {
+        let mut chunker = Chunker::new(body);
+        let mut data = Vec::new();
+        while let Some((_, chunk)) = chunker
+            .next_chunk(self.chunk_size)
+            .await
+            .map_err(|e| e.into_response())?
+        {
+            data.extend_from_slice(chunk);
+        }
+
+        let mut result = chunker.finish();
+        if let Err(e) = verify(&result.md5, &result.checksums) {
+            return Err(e.into_response());
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        result.r#ref = uuid::Uuid::new_v4().simple().to_string();
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(result.r#ref.clone(), Bytes::from(data));
+        Ok(Some(result))
+    }
+
+    fn read_range(&self, backend_key: &str, range: Range<i64>, _chunk_start: i64) -> ByteStream {
+        let object = self.objects.lock().unwrap().get(backend_key).cloned();
+        let item = match object {
+            Some(v) if range.end as usize <= v.len() => {
+                Ok(v.slice(range.start as usize..range.end as usize))
+            }
+            Some(_) => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+        Box::pin(tokio_stream::once(item))
+    }
+
+    async fn delete(&self, backend_key: &str) -> Result<(), S3Error> {
+        self.objects.lock().unwrap().remove(backend_key);
+        Ok(())
+    }
+
+    async fn health(&self) -> bool {
+        true
+    }
+}
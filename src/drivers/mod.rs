@@ -1 +1,142 @@
+use std::{collections::HashMap, ops::Range, pin::Pin, sync::Arc, time::Duration};
+
+use axum::{
+    body::{BodyDataStream, Bytes},
+    response::Response,
+};
+use futures_core::Stream;
+
+use crate::s3serv::{checksum::Checksums, error::S3Error};
+
+mod chunker;
+pub mod fs;
+pub mod memory;
+pub mod s3;
 pub mod ton;
+
+/// Reads a duration given in (fractional) seconds.
+fn secs_from_env(name: &str) -> Option<Duration> {
+    let value = std::env::var(name).ok()?;
+    let secs = value
+        .parse::<f64>()
+        .unwrap_or_else(|_| panic!("{} must be a number of seconds", name));
+    Some(Duration::from_secs_f64(secs))
+}
+
+pub struct ChunkInfo {
+    pub range: std::ops::Range<i64>,
+    pub md5: [u8; 16],
+    pub sha256: [u8; 32],
+}
+
+pub struct UploadResult {
+    pub r#ref: String,
+    pub md5: [u8; 16],
+    pub size: u64,
+    pub checksums: Checksums,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+/// Checks the MD5 and checksums of a whole body before its upload is committed.
+pub type UploadVerifier<'a> = &'a (dyn Fn(&[u8; 16], &Checksums) -> Result<(), S3Error> + Sync);
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// A backend storing object data. Objects are identified by the `backend_key` the driver
+/// returned on upload, and `file_data_parts.driver` records which driver that was.
+#[async_trait::async_trait]
+pub trait StorageDriver: Send + Sync {
+    /// Stored in `file_data_parts.driver`, must never change.
+    fn name(&self) -> &'static str;
+
+    /// Stores the body as a new backend object. Empty bodies are not stored and give `None`.
+    ///
+    /// `verify` is called before the object is committed, an error abandons it.
+    async fn upload(
+        &self,
+        body: &mut BodyDataStream,
+        verify: UploadVerifier<'_>,
+    ) -> Result<Option<UploadResult>, Response>;
+
+    /// Reads `range` of a backend object.
+    ///
+    /// `chunk_start` is the start of the recorded chunk containing `range.start`, for
+    /// backends which can only read from chunk boundaries.
+    fn read_range(&self, backend_key: &str, range: Range<i64>, chunk_start: i64) -> ByteStream;
+
+    /// Removes a backend object which is not referenced anymore, where the backend
+    /// supports deleting at all.
+    async fn delete(&self, backend_key: &str) -> Result<(), S3Error>;
+
+    /// Whether the backend is reachable.
+    async fn health(&self) -> bool;
+}
+
+/// The configured drivers. New data goes to the default one, existing data is read
+/// from whichever driver stored it.
+pub struct Drivers {
+    default: Arc<dyn StorageDriver>,
+    by_name: HashMap<&'static str, Arc<dyn StorageDriver>>,
+}
+
+impl Drivers {
+    pub fn new(default: Arc<dyn StorageDriver>) -> Self {
+        let mut by_name = HashMap::new();
+        by_name.insert(default.name(), default.clone());
+        Drivers { default, by_name }
+    }
+
+    /// Registers a driver for reading the data it stored, without making it the default.
+    pub fn with(mut self, driver: Arc<dyn StorageDriver>) -> Self {
+        self.by_name.entry(driver.name()).or_insert(driver);
+        self
+    }
+
+    /// Configures the drivers from the environment.
+    ///
+    /// `STORAGE_DRIVER` names the default driver, `ton` unless set. ton is configured by
+    /// [`ton::TonConfig::from_env`]. The `fs` driver is
+    /// available when `FS_STORAGE_DIR` is set, the `s3` one when `S3_UPSTREAM_ENDPOINT`
+    /// is (see [`s3::S3Config::from_env`]). ton stays registered so data stored before
+    /// switching remains readable.
+    pub fn from_env() -> Self {
+        let mut available: Vec<Arc<dyn StorageDriver>> =
+            vec![Arc::new(ton::TonDriver::new(ton::TonConfig::from_env()))];
+        if let Some(dir) = std::env::var_os("FS_STORAGE_DIR") {
+            available.push(Arc::new(fs::FsDriver::new(dir.into())));
+        }
+        if let Some(config) = s3::S3Config::from_env() {
+            available.push(Arc::new(s3::S3Driver::new(config)));
+        }
+
+        let name = std::env::var("STORAGE_DRIVER").unwrap_or_else(|_| "ton".to_string());
+        let default = available
+            .iter()
+            .find(|v| v.name() == name)
+            .unwrap_or_else(|| panic!("STORAGE_DRIVER {} is not configured", name))
+            .clone();
+
+        available
+            .into_iter()
+            .fold(Drivers::new(default), |drivers, v| drivers.with(v))
+    }
+
+    pub fn default_driver(&self) -> &Arc<dyn StorageDriver> {
+        &self.default
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn StorageDriver>> {
+        self.by_name.get(name)
+    }
+
+    /// Logs the drivers which don't answer, at startup.
+    pub async fn check_health(&self) {
+        for driver in self.by_name.values() {
+            if driver.health().await {
+                tracing::info!("storage driver {} is healthy", driver.name());
+            } else {
+                tracing::warn!("storage driver {} is not reachable", driver.name());
+            }
+        }
+    }
+}
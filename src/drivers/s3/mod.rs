@@ -0,0 +1,508 @@
+use std::{ops::Range, time::Duration};
+
+use axum::{
+    body::{BodyDataStream, Bytes},
+    response::{IntoResponse, Response},
+};
+use reqwest::{header::HeaderMap, Method, StatusCode, Url};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+
+use super::{
+    chunker::Chunker, secs_from_env, ByteStream, StorageDriver, UploadResult, UploadVerifier,
+};
+use crate::s3serv::{
+    auth::sigv4::{self, CanonicalRequest, CredentialScope},
+    error::S3Error,
+};
+
+/// Bodies above this are uploaded with multipart, in parts of this size.
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+#[derive(serde::Deserialize)]
+struct InitiateMultipartUploadResult {
+    #[serde(rename = "UploadId")]
+    upload_id: String,
+}
+
+#[derive(serde::Serialize)]
+struct CompleteMultipartUpload {
+    #[serde(rename = "Part")]
+    parts: Vec<CompletedPart>,
+}
+
+#[derive(serde::Serialize)]
+struct CompletedPart {
+    #[serde(rename = "PartNumber")]
+    part_number: usize,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+/// Where the upstream bucket is and how to sign for it.
+pub struct S3Config {
+    /// e.g. `https://s3.us-east-1.amazonaws.com`, buckets are addressed path-style.
+    pub endpoint: Url,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prepended to the key of every upstream object.
+    pub prefix: String,
+    pub connect_timeout: Duration,
+    /// Longest wait for any read from a connection, not for the whole response.
+    pub read_timeout: Duration,
+}
+
+impl S3Config {
+    /// Reads `S3_UPSTREAM_ENDPOINT`, `S3_UPSTREAM_BUCKET`, `S3_UPSTREAM_ACCESS_KEY_ID`,
+    /// `S3_UPSTREAM_SECRET_ACCESS_KEY` and optionally `S3_UPSTREAM_REGION`,
+    /// `S3_UPSTREAM_PREFIX`, `S3_UPSTREAM_CONNECT_TIMEOUT_SECS` and
+    /// `S3_UPSTREAM_READ_TIMEOUT_SECS`. `None` when no endpoint is set.
+    pub fn from_env() -> Option<Self> {
+        let endpoint = std::env::var("S3_UPSTREAM_ENDPOINT").ok()?;
+        let var =
+            |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} must be set", name));
+        let region = std::env::var("S3_UPSTREAM_REGION").unwrap_or_else(|_| "us-east-1".into());
+        Some(S3Config {
+            endpoint: endpoint
+                .parse()
+                .expect("S3_UPSTREAM_ENDPOINT must be a valid URL"),
+            bucket: var("S3_UPSTREAM_BUCKET"),
+            region,
+            access_key_id: var("S3_UPSTREAM_ACCESS_KEY_ID"),
+            secret_access_key: var("S3_UPSTREAM_SECRET_ACCESS_KEY"),
+            prefix: std::env::var("S3_UPSTREAM_PREFIX").unwrap_or_default(),
+            connect_timeout: secs_from_env("S3_UPSTREAM_CONNECT_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: secs_from_env("S3_UPSTREAM_READ_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_READ_TIMEOUT),
+        })
+    }
+}
+
+/// Stores every data part as an object in a bucket of another S3 server.
+pub struct S3Driver {
+    client: reqwest::Client,
+    config: S3Config,
+}
+
+impl S3Driver {
+    pub fn new(config: S3Config) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .read_timeout(config.read_timeout)
+            .build()
+            .expect("Failed to build the upstream client");
+        S3Driver { client, config }
+    }
+
+    fn url(&self, key: Option<&str>, query: &str) -> Url {
+        let mut url = self.config.endpoint.clone();
+        {
+            let mut segments = url.path_segments_mut().expect("endpoint is a base URL");
+            segments.pop_if_empty().push(&self.config.bucket);
+            if let Some(key) = key {
+                segments.extend(key.split('/'));
+            }
+        }
+        if !query.is_empty() {
+            url.set_query(Some(query));
+        }
+        url
+    }
+
+    /// Builds a request signed with SigV4. `headers` must not need signing.
+    fn request(
+        &self,
+        method: Method,
+        url: Url,
+        mut headers: HeaderMap,
+        body: Bytes,
+    ) -> reqwest::RequestBuilder {
+        let now = chrono::Utc::now();
+        let timestamp = now.format(sigv4::TIMESTAMP_FORMAT).to_string();
+        let scope = CredentialScope {
+            date: now.format("%Y%m%d").to_string(),
+            region: self.config.region.clone(),
+        };
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        headers.insert("host", host.parse().unwrap());
+        headers.insert("x-amz-content-sha256", payload_hash.parse().unwrap());
+        headers.insert("x-amz-date", timestamp.parse().unwrap());
+
+        let canonical_request = CanonicalRequest {
+            method: method.as_str(),
+            path: url.path(),
+            query: sigv4::canonical_query(url.query(), None),
+            headers: sigv4::canonical_headers(
+                &headers,
+                &SIGNED_HEADERS.split(';').collect::<Vec<_>>(),
+            ),
+            signed_headers: SIGNED_HEADERS,
+            payload_hash: &payload_hash,
+        }
+        .to_canonical_string();
+        let string_to_sign = sigv4::string_to_sign(&timestamp, &scope, &canonical_request);
+        let signing_key = scope.signing_key(&self.config.secret_access_key);
+        let authorization = format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            sigv4::ALGORITHM,
+            self.config.access_key_id,
+            scope.to_scope_string(),
+            SIGNED_HEADERS,
+            sigv4::sign(&signing_key, &string_to_sign)
+        );
+        headers.insert("authorization", authorization.parse().unwrap());
+
+        self.client.request(method, url).headers(headers).body(body)
+    }
+
+    /// Sends a request and fails on anything but a success status, logging `what`.
+    async fn send_ok(
+        &self,
+        what: &str,
+        method: Method,
+        url: Url,
+        body: Bytes,
+    ) -> Result<reqwest::Response, S3Error> {
+        let res = self
+            .request(method, url, HeaderMap::new(), body)
+            .send()
+            .await;
+        match res {
+            Ok(v) => {
+                if !v.status().is_success() {
+                    let status = v.status();
+                    let v = v.text().await.unwrap_or_default();
+                    tracing::error!("Failed to {}: {}, {}", what, status, v);
+                    return Err(S3Error::InternalError);
+                }
+                Ok(v)
+            }
+            Err(e) => {
+                tracing::error!("Failed to {}: {:?}", what, e);
+                Err(S3Error::InternalError)
+            }
+        }
+    }
+
+    async fn put_object(&self, key: &str, body: Bytes) -> Result<(), S3Error> {
+        self.send_ok("put object", Method::PUT, self.url(Some(key), ""), body)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_multipart_upload(&self, key: &str) -> Result<String, S3Error> {
+        let res = self
+            .send_ok(
+                "create multipart upload",
+                Method::POST,
+                self.url(Some(key), "uploads"),
+                Bytes::new(),
+            )
+            .await?;
+        let text = res.text().await.unwrap_or_default();
+        match quick_xml::de::from_str::<InitiateMultipartUploadResult>(&text) {
+            Ok(v) => Ok(v.upload_id),
+            Err(e) => {
+                tracing::error!("Failed to parse InitiateMultipartUploadResult: {:?}", e);
+                Err(S3Error::InternalError)
+            }
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        body: Bytes,
+    ) -> Result<CompletedPart, S3Error> {
+        let query = format!("partNumber={}&uploadId={}", part_number, upload_id);
+        let res = self
+            .send_ok(
+                "upload part",
+                Method::PUT,
+                self.url(Some(key), &query),
+                body,
+            )
+            .await?;
+        let etag = res.headers().get("etag").and_then(|v| v.to_str().ok());
+        match etag {
+            Some(etag) => Ok(CompletedPart {
+                part_number,
+                etag: etag.to_string(),
+            }),
+            None => {
+                tracing::error!("Upstream returned no ETag for part {}", part_number);
+                Err(S3Error::InternalError)
+            }
+        }
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+    ) -> Result<(), S3Error> {
+        let mut buffer = String::new();
+        let serializer = quick_xml::se::Serializer::new(&mut buffer);
+        CompleteMultipartUpload { parts }
+            .serialize(serializer)
+            .expect("Failed to serialize.");
+
+        let query = format!("uploadId={}", upload_id);
+        let res = self
+            .send_ok(
+                "complete multipart upload",
+                Method::POST,
+                self.url(Some(key), &query),
+                Bytes::from(buffer),
+            )
+            .await?;
+        // S3 may report a failure in the body of a 200 response
+        let text = res.text().await.unwrap_or_default();
+        if text.contains("<Error>") {
+            tracing::error!("Failed to complete multipart upload: {}", text);
+            return Err(S3Error::InternalError);
+        }
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) {
+        let query = format!("uploadId={}", upload_id);
+        let _ = self
+            .send_ok(
+                "abort multipart upload",
+                Method::DELETE,
+                self.url(Some(key), &query),
+                Bytes::new(),
+            )
+            .await;
+    }
+
+    /// Uploads `first` and the rest of the body as parts of a multipart upload, which
+    /// is only completed once `verify` accepts the body.
+    async fn upload_multipart(
+        &self,
+        key: &str,
+        first: Bytes,
+        mut chunker: Chunker<'_>,
+        verify: UploadVerifier<'_>,
+    ) -> Result<UploadResult, Response> {
+        let upload_id = self
+            .create_multipart_upload(key)
+            .await
+            .map_err(|e| e.into_response())?;
+
+        let uploaded = async {
+            let mut parts = vec![self
+                .upload_part(key, &upload_id, 1, first)
+                .await
+                .map_err(|e| e.into_response())?];
+            while let Some((_, chunk)) = chunker
+                .next_chunk(PART_SIZE)
+                .await
+                .map_err(|e| e.into_response())?
+            {
+                let part = self
+                    .upload_part(
+                        key,
+                        &upload_id,
+                        parts.len() + 1,
+                        Bytes::copy_from_slice(chunk),
+                    )
+                    .await
+                    .map_err(|e| e.into_response())?;
+                parts.push(part);
+            }
+            Ok(parts)
+        }
+        .await;
+
+        let parts = match uploaded {
+            Ok(v) => v,
+            Err(e) => {
+                self.abort_multipart_upload(key, &upload_id).await;
+                return Err(e);
+            }
+        };
+
+        let result = chunker.finish();
+        if let Err(e) = verify(&result.md5, &result.checksums) {
+            tracing::info!("Abandoning upload: {}", e);
+            self.abort_multipart_upload(key, &upload_id).await;
+            return Err(e.into_response());
+        }
+
+        if let Err(e) = self.complete_multipart_upload(key, &upload_id, parts).await {
+            self.abort_multipart_upload(key, &upload_id).await;
+            return Err(e.into_response());
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageDriver for S3Driver {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn upload(
+        &self,
+        body: &mut BodyDataStream,
+        verify: UploadVerifier<'_>,
+    ) -> Result<Option<UploadResult>, Response> {
+        let mut chunker = Chunker::new(body);
+        if chunker.is_empty().await.map_err(|e| e.into_response())? {
+            let result = chunker.finish();
+            return match verify(&result.md5, &result.checksums) {
+                Ok(()) => Ok(None),
+                Err(e) => Err(e.into_response()),
+            };
+        }
+
+        let key = format!("{}{}", self.config.prefix, uuid::Uuid::new_v4().simple());
+
+        let first = match chunker.next_chunk(PART_SIZE).await {
+            Ok(Some((_, chunk))) => Bytes::copy_from_slice(chunk),
+            Ok(None) => unreachable!("the body is not empty"),
+            Err(e) => return Err(e.into_response()),
+        };
+
+        let mut result = if chunker.is_empty().await.map_err(|e| e.into_response())? {
+            // fits in a single part, which is buffered until verified
+            let result = chunker.finish();
+            if let Err(e) = verify(&result.md5, &result.checksums) {
+                tracing::info!("Abandoning upload: {}", e);
+                return Err(e.into_response());
+            }
+            self.put_object(&key, first)
+                .await
+                .map_err(|e| e.into_response())?;
+            result
+        } else {
+            self.upload_multipart(&key, first, chunker, verify).await?
+        };
+
+        result.r#ref = key;
+        Ok(Some(result))
+    }
+
+    fn read_range(&self, backend_key: &str, range: Range<i64>, _chunk_start: i64) -> ByteStream {
+        let mut headers = HeaderMap::new();
+        let value = format!("bytes={}-{}", range.start, range.end - 1);
+        headers.insert("range", value.parse().unwrap());
+        let request = self.request(
+            Method::GET,
+            self.url(Some(backend_key), ""),
+            headers,
+            Bytes::new(),
+        );
+        let backend_key = backend_key.to_string();
+
+        let stream = async_stream::stream! {
+            let res = match request.send().await.and_then(|v| v.error_for_status()) {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Failed to fetch upstream object: {:?}", e);
+                    yield Err(std::io::Error::other(e));
+                    return;
+                }
+            };
+
+            // an upstream ignoring the range would send other bytes than asked for
+            let expected = format!("bytes {}-{}/", range.start, range.end - 1);
+            let matches = match res.status() {
+                StatusCode::PARTIAL_CONTENT => res
+                    .headers()
+                    .get("content-range")
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with(&expected)),
+                _ => range.start == 0 && res.content_length() == Some((range.end - range.start) as u64),
+            };
+            if !matches {
+                tracing::error!(
+                    "Upstream answered {} to the range {}..{} of {} with {:?}",
+                    res.status(),
+                    range.start,
+                    range.end,
+                    backend_key,
+                    res.headers().get("content-range")
+                );
+                yield Err(std::io::Error::other("upstream did not return the requested range"));
+                return;
+            }
+
+            let mut remaining = range.end - range.start;
+            let mut body = res.bytes_stream();
+            while let Some(v) = body.next().await {
+                match v {
+                    Ok(v) => {
+                        remaining -= v.len() as i64;
+                        yield Ok(v);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to read upstream object: {:?}", e);
+                        yield Err(std::io::Error::other(e));
+                        return;
+                    }
+                }
+            }
+            if remaining != 0 {
+                tracing::error!("Upstream object {} is {} bytes short", backend_key, remaining);
+                yield Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+            }
+        };
+        Box::pin(stream)
+    }
+
+    async fn delete(&self, backend_key: &str) -> Result<(), S3Error> {
+        // deleting a missing object succeeds as well
+        self.send_ok(
+            "delete upstream object",
+            Method::DELETE,
+            self.url(Some(backend_key), ""),
+            Bytes::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn health(&self) -> bool {
+        let res = self
+            .request(
+                Method::HEAD,
+                self.url(None, ""),
+                HeaderMap::new(),
+                Bytes::new(),
+            )
+            .send()
+            .await;
+        match res {
+            Ok(v) if v.status().is_success() => true,
+            Ok(v) => {
+                tracing::debug!("upstream bucket is not accessible: {}", v.status());
+                false
+            }
+            Err(e) => {
+                tracing::debug!("upstream is not reachable: {:?}", e);
+                false
+            }
+        }
+    }
+}
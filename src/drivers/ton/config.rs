@@ -0,0 +1,104 @@
+use std::{path::PathBuf, time::Duration};
+
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+
+use super::RetryPolicy;
+use crate::drivers::secs_from_env;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:4000";
+
+/// How to reach ton.
+pub struct TonConfig {
+    /// Used in turn, a failing server is skipped for the next one.
+    pub base_urls: Vec<String>,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+    pub connect_timeout: Duration,
+    /// Longest wait for any read from a connection, not for the whole response.
+    pub read_timeout: Duration,
+    /// A PEM file with CA certificates to trust in addition to the system ones.
+    pub ca_cert: Option<PathBuf>,
+    pub accept_invalid_certs: bool,
+    pub retry: RetryPolicy,
+}
+
+impl Default for TonConfig {
+    fn default() -> Self {
+        TonConfig {
+            base_urls: vec![DEFAULT_BASE_URL.to_string()],
+            token: None,
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(60),
+            ca_cert: None,
+            accept_invalid_certs: false,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl TonConfig {
+    /// Reads `TON_BASE_URLS` (comma separated), `TON_TOKEN`, `TON_CONNECT_TIMEOUT_SECS`,
+    /// `TON_READ_TIMEOUT_SECS`, `TON_CA_CERT`, `TON_ACCEPT_INVALID_CERTS`, `TON_RETRIES`,
+    /// `TON_RETRY_BASE_DELAY_SECS` and `TON_RETRY_MAX_DELAY_SECS`, all optional.
+    pub fn from_env() -> Self {
+        let default = TonConfig::default();
+        let base_urls = match std::env::var("TON_BASE_URLS") {
+            Ok(v) => v
+                .split(',')
+                .map(|v| v.trim().trim_end_matches('/').to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            Err(_) => default.base_urls,
+        };
+        assert!(!base_urls.is_empty(), "TON_BASE_URLS must not be empty");
+
+        TonConfig {
+            base_urls,
+            token: std::env::var("TON_TOKEN").ok(),
+            connect_timeout: secs_from_env("TON_CONNECT_TIMEOUT_SECS")
+                .unwrap_or(default.connect_timeout),
+            read_timeout: secs_from_env("TON_READ_TIMEOUT_SECS").unwrap_or(default.read_timeout),
+            ca_cert: std::env::var_os("TON_CA_CERT").map(PathBuf::from),
+            accept_invalid_certs: std::env::var("TON_ACCEPT_INVALID_CERTS")
+                .is_ok_and(|v| v == "1" || v == "true"),
+            retry: RetryPolicy {
+                retries: std::env::var("TON_RETRIES")
+                    .map(|v| v.parse().expect("TON_RETRIES must be a number"))
+                    .unwrap_or(default.retry.retries),
+                base_delay: secs_from_env("TON_RETRY_BASE_DELAY_SECS")
+                    .unwrap_or(default.retry.base_delay),
+                max_delay: secs_from_env("TON_RETRY_MAX_DELAY_SECS")
+                    .unwrap_or(default.retry.max_delay),
+            },
+        }
+    }
+
+    /// The client every request to ton goes through, so connections are pooled.
+    pub fn build_client(&self) -> reqwest::Client {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &self.token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .expect("TON_TOKEN must be a valid header value");
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+
+        if let Some(path) = &self.ca_cert {
+            let pem = std::fs::read(path)
+                .unwrap_or_else(|e| panic!("Failed to read {:?}: {:?}", path, e));
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .unwrap_or_else(|e| panic!("Failed to parse {:?}: {:?}", path, e));
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        builder.build().expect("Failed to build the ton client")
+    }
+}
@@ -1,14 +1,28 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
 use axum::{
     body::{BodyDataStream, Bytes},
     response::{IntoResponse, Response},
 };
-use md5::Digest;
-use sha2::Sha256;
-use sqlx::PgTransaction;
 use tokio_stream::StreamExt;
 
+use super::{chunker::Chunker, ByteStream, StorageDriver, UploadResult, UploadVerifier};
 use crate::s3serv::error::S3Error;
 
+mod config;
+mod retry;
+
+pub use config::TonConfig;
+pub use retry::RetryPolicy;
+use retry::{with_retry, Failure};
+
 #[derive(serde::Deserialize)]
 struct SessionStartResponse {
     token: String,
@@ -26,217 +40,345 @@ struct UploadFinalizeResponse {
     r#ref: String,
 }
 
-pub struct ChunkInfo {
-    pub range: std::ops::Range<i64>,
-    pub md5: [u8; 16],
-    pub sha256: [u8; 32],
+/// A server which failed is only tried after the others for this long.
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(10);
+
+struct Endpoint {
+    base_url: String,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn recently_failed(&self) -> bool {
+        self.failed_at
+            .lock()
+            .unwrap()
+            .is_some_and(|v| v.elapsed() < FAILURE_COOLDOWN)
+    }
+
+    fn mark_failed(&self) {
+        *self.failed_at.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// The ton servers. Every one of them serves every file, but an upload session only
+/// exists on the server it was started on.
+struct Endpoints {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+impl Endpoints {
+    fn new(base_urls: Vec<String>) -> Self {
+        Endpoints {
+            endpoints: base_urls
+                .into_iter()
+                .map(|base_url| Endpoint {
+                    base_url,
+                    failed_at: Mutex::new(None),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// All servers, starting from the next one in turn, the recently failed ones last.
+    fn rotation(&self) -> Vec<&Endpoint> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.endpoints.len();
+        let mut rotation = (0..len)
+            .map(|i| &self.endpoints[(start + i) % len])
+            .collect::<Vec<_>>();
+        rotation.sort_by_key(|v| v.recently_failed());
+        rotation
+    }
+}
+
+/// What every request to ton needs.
+struct Ton {
+    client: reqwest::Client,
+    endpoints: Endpoints,
+    retry: RetryPolicy,
 }
 
-pub struct UploadResult {
-    pub r#ref: String,
-    pub md5: [u8; 16],
-    pub size: u64,
-    pub chunks: Vec<ChunkInfo>,
+/// Sends `request` to one server after another until one answers without a transient
+/// failure, returning that server and its response.
+async fn send_with_failover<'a>(
+    endpoints: &'a Endpoints,
+    what: &str,
+    request: impl Fn(&str) -> reqwest::RequestBuilder,
+) -> Result<(&'a Endpoint, reqwest::Response), Failure> {
+    for endpoint in endpoints.rotation() {
+        let res = request(&endpoint.base_url).send().await;
+        match retry::check_response(res, what, &endpoint.base_url).await {
+            Ok(v) => return Ok((endpoint, v)),
+            Err(Failure::Transient) => endpoint.mark_failed(),
+            Err(Failure::Permanent) => return Err(Failure::Permanent),
+        }
+    }
+    Err(Failure::Transient)
 }
 
-#[tracing::instrument(skip(client, session, bytes), fields())]
+#[tracing::instrument(skip(ton, endpoint, session, bytes), fields())]
 async fn upload_chunk(
-    client: &reqwest::Client,
+    ton: &Ton,
+    endpoint: &Endpoint,
     session: &SessionStartResponse,
     offset: u64,
-    bytes: &[u8],
+    bytes: Bytes,
 ) -> Result<(), Response> {
     assert!(bytes.len() <= session.chunk_size);
-    let upload_chunk_res = client
-        .post("http://localhost:4000/v1/upload/chunk")
-        .query(&[("token", &session.token), ("offset", &offset.to_string())])
-        .body(bytes.to_vec()) // TODO: why I need to copy it?
-        .send()
-        .await;
-
-    let upload_chunk_res = match upload_chunk_res {
-        Ok(v) => {
-            if !v.status().is_success() {
-                let status = v.status();
-                let v = v.text().await.unwrap_or_default();
-                tracing::error!("Failed to upload chunk: {}, {}", status, v);
-                return Err(S3Error::InternalError.into_response());
+    // the session only exists on this server, so retry there
+    with_retry(&ton.retry, "upload chunk", || async {
+        let res = ton
+            .client
+            .post(format!("{}/v1/upload/chunk", endpoint.base_url))
+            .query(&[("token", &session.token), ("offset", &offset.to_string())])
+            .body(bytes.clone())
+            .send()
+            .await;
+        let res = retry::check_response(res, "upload chunk", &endpoint.base_url).await?;
+        tracing::debug!("chunk uploaded, {}", res.status());
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.into_response())
+}
+
+/// Uploads the body to ton. Empty bodies are not uploaded and give `None`.
+///
+/// `verify` checks the digests of the whole body before the upload is finalized, so
+/// a rejected body is never committed and its session is left to expire.
+async fn upload_from_stream(
+    ton: &Ton,
+    body: &mut BodyDataStream,
+    verify: UploadVerifier<'_>,
+) -> Result<Option<UploadResult>, Response> {
+    let mut chunker = Chunker::new(body);
+    if chunker.is_empty().await.map_err(|e| e.into_response())? {
+        let result = chunker.finish();
+        return match verify(&result.md5, &result.checksums) {
+            Ok(()) => Ok(None),
+            Err(e) => Err(e.into_response()),
+        };
+    }
+
+    // the rest of the upload goes to the server which holds the session
+    let (endpoint, session) = with_retry(&ton.retry, "start upload", || async {
+        let (endpoint, res) = send_with_failover(&ton.endpoints, "start upload", |base_url| {
+            ton.client.post(format!("{}/v1/upload/start", base_url))
+        })
+        .await?;
+        match res.json::<SessionStartResponse>().await {
+            Ok(v) => Ok((endpoint, v)),
+            Err(e) => {
+                tracing::error!("Failed to parse session start response: {:?}", e);
+                Err(Failure::Permanent)
             }
-            v
-        }
-        Err(e) => {
-            tracing::error!("Failed to upload chunk: {:?}", e);
-            return Err(S3Error::InternalError.into_response());
         }
-    };
+    })
+    .await
+    .map_err(|e| e.into_response())?;
+
+    if session.chunk_size < 1 {
+        tracing::error!("Chunk size is too small");
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    while let Some((offset, chunk)) = chunker
+        .next_chunk(session.chunk_size)
+        .await
+        .map_err(|e| e.into_response())?
+    {
+        tracing::debug!("uploading chunk len={}, offset={}", chunk.len(), offset);
+        // copied once, so that retries resend it without copying again
+        let chunk = Bytes::copy_from_slice(chunk);
+        upload_chunk(ton, endpoint, &session, offset, chunk).await?;
+    }
 
-    tracing::debug!("chunk uploaded, {}", upload_chunk_res.status());
+    let mut result = chunker.finish();
+
+    if let Err(e) = verify(&result.md5, &result.checksums) {
+        tracing::info!("Abandoning upload: {}", e);
+        return Err(e.into_response());
+    }
 
-    Ok(())
+    // a finalize which succeeded but whose answer got lost fails when retried, as the
+    // session is gone, so the upload fails rather than being stored twice
+    let finalized = with_retry(&ton.retry, "finish upload", || async {
+        let res = ton
+            .client
+            .post(format!("{}/v1/upload/finalize", endpoint.base_url))
+            .query(&[("token", &session.token)])
+            .json(&UploadFinalizeRequest {
+                name: "sagisawa.bin".to_string(),
+                md5: hex::encode(result.md5),
+            })
+            .send()
+            .await;
+        let res = retry::check_response(res, "finish upload", &endpoint.base_url).await?;
+        match res.json::<UploadFinalizeResponse>().await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                tracing::error!("Failed to parse session finish response: {:?}", e);
+                Err(Failure::Permanent)
+            }
+        }
+    })
+    .await
+    .map_err(|e| e.into_response())?;
+
+    result.r#ref = finalized.r#ref;
+    Ok(Some(result))
 }
 
-pub async fn upload_from_stream(
-    pool: &PgTransaction<'_>,
-    body: &mut BodyDataStream,
-) -> Result<Option<UploadResult>, Response> {
-    let first = loop {
-        let first = body.next().await;
-        match first {
-            None => return Ok(None),
-            Some(Ok(v)) => {
-                if v.is_empty() {
-                    continue;
+/// Reads chunk after chunk from `chunk_start`, as ton only serves whole chunks.
+///
+/// A chunk which fails to arrive, even halfway through, is fetched again from any
+/// server, and the stream goes on from the last byte it delivered.
+fn read_chunks(
+    ton: Arc<Ton>,
+    backend_key: String,
+    want: Range<i64>,
+    chunk_start: i64,
+) -> ByteStream {
+    let stream = async_stream::stream! {
+        let mut chunk_start = chunk_start.min(want.start);
+        // the next byte to deliver
+        let mut delivered = want.start;
+        let mut failures = 0;
+        while delivered < want.end {
+            if failures > 0 {
+                if failures > ton.retry.retries {
+                    tracing::error!("Failed to fetch file part: giving up after {} attempts", failures);
+                    yield Err(std::io::Error::other("ton is not available"));
+                    return;
                 }
-                break v;
-            }
-            Some(Err(e)) => {
-                tracing::error!("Failed to read first frame: {:?}", e);
-                return Err(S3Error::InternalError.into_response());
+                let delay = ton.retry.delay(failures - 1);
+                tracing::info!("Resuming at {} in {:?}", delivered, delay);
+                tokio::time::sleep(delay).await;
             }
-        }
-    };
 
-    let client = reqwest::Client::new();
+            let res = send_with_failover(&ton.endpoints, "fetch file part", |base_url| {
+                ton.client.get(format!("{}/v1/files/{}/chunks/{}", base_url, backend_key, chunk_start))
+            })
+            .await;
 
-    let session_result = client
-        .post("http://localhost:4000/v1/upload/start")
-        .send()
-        .await;
+            let (endpoint, res) = match res {
+                Ok(v) => v,
+                Err(Failure::Transient) => {
+                    failures += 1;
+                    continue;
+                }
+                Err(Failure::Permanent) => {
+                    yield Err(std::io::Error::other("ton refused to serve the file"));
+                    return;
+                }
+            };
 
-    let session_result = match session_result {
-        Ok(v) => {
-            if !v.status().is_success() {
-                let status = v.status();
-                let v = v.text().await.unwrap_or_default();
-                tracing::error!("Failed to start upload: {}, {}", status, v);
-                return Err(S3Error::InternalError.into_response());
+            let mut position = chunk_start;
+            let mut body = res.bytes_stream();
+            let mut interrupted = false;
+            while let Some(part) = body.next().await {
+                let part = match part {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("Failed to read file part from {}: {:?}", endpoint.base_url, e);
+                        endpoint.mark_failed();
+                        interrupted = true;
+                        break;
+                    }
+                };
+                let range = position..position + part.len() as i64;
+                position = range.end;
+                let from = delivered.max(range.start);
+                let to = want.end.min(range.end);
+                if from < to {
+                    delivered = to;
+                    failures = 0;
+                    yield Ok::<Bytes, std::io::Error>(
+                        part.slice((from - range.start) as usize..(to - range.start) as usize),
+                    );
+                }
+                if position >= want.end {
+                    break;
+                }
             }
-            v
-        }
-        Err(e) => {
-            tracing::error!("Failed to start upload: {:?}", e);
-            return Err(S3Error::InternalError.into_response());
-        }
-    };
 
-    let session_result = session_result.json::<SessionStartResponse>().await;
+            if interrupted {
+                failures += 1;
+                continue;
+            }
 
-    let session = match session_result {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("Failed to parse session start response: {:?}", e);
-            return Err(S3Error::InternalError.into_response());
+            if position == chunk_start {
+                tracing::error!("Backend returned empty chunk at offset {}", chunk_start);
+                yield Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+                return;
+            }
+            chunk_start = position;
         }
     };
+    Box::pin(stream)
+}
 
-    let mut buf = Vec::<u8>::with_capacity(session.chunk_size);
+/// Stores objects in ton.
+pub struct TonDriver {
+    ton: Arc<Ton>,
+}
 
-    if session.chunk_size < 1 {
-        tracing::error!("Chunk size is too small");
-        return Err(S3Error::InternalError.into_response());
+impl TonDriver {
+    pub fn new(config: TonConfig) -> Self {
+        TonDriver {
+            ton: Arc::new(Ton {
+                client: config.build_client(),
+                endpoints: Endpoints::new(config.base_urls),
+                retry: config.retry,
+            }),
+        }
     }
+}
 
-    let mut current = first;
-    let mut offset = 0;
-    let mut all_chunks: Vec<ChunkInfo> = Vec::new();
-    let mut hasher = md5::Md5::new();
-    loop {
-        if (buf.len() + current.len()) > session.chunk_size {
-            let available = session.chunk_size - buf.len();
-            buf.extend_from_slice(&current[0..available]);
-            tracing::debug!(
-                "uploading chunk len={} + {}, offset={}",
-                buf.len(),
-                current.len(),
-                offset
-            );
-            hasher.update(&buf);
-            let chunk_md5 = md5::Md5::digest(&buf);
-            let chunk_sha256 = Sha256::digest(&buf);
-            all_chunks.push(ChunkInfo {
-                range: (offset as i64)..(offset as i64) + (buf.len() as i64),
-                md5: chunk_md5.into(),
-                sha256: chunk_sha256.into(),
-            });
-            upload_chunk(&client, &session, offset, &buf).await?;
-            offset += buf.len() as u64;
-            current = Bytes::from(current[available..].to_vec());
-            tracing::debug!("new current len={}", current.len());
-            buf.clear();
-            continue;
-        } else {
-            tracing::debug!(
-                "adding current thing to buffer {} + {}",
-                buf.len(),
-                current.len()
-            );
-            buf.extend_from_slice(&current);
-        }
+#[async_trait::async_trait]
+impl StorageDriver for TonDriver {
+    fn name(&self) -> &'static str {
+        "ton"
+    }
 
-        let next = body.next().await;
-        match next {
-            None => {
-                tracing::debug!("End of stream");
-                break;
-            }
-            Some(Ok(v)) => {
-                tracing::debug!("Read frame {}", v.len());
-                current = v;
-            }
-            Some(Err(e)) => {
-                tracing::error!("Failed to read frame: {:?}", e);
-                return Err(S3Error::InternalError.into_response());
-            }
-        }
+    async fn upload(
+        &self,
+        body: &mut BodyDataStream,
+        verify: UploadVerifier<'_>,
+    ) -> Result<Option<UploadResult>, Response> {
+        upload_from_stream(&self.ton, body, verify).await
     }
 
-    if !buf.is_empty() {
-        hasher.update(&buf);
-        upload_chunk(&client, &session, offset, &buf).await?;
-        offset += buf.len() as u64;
+    fn read_range(&self, backend_key: &str, range: Range<i64>, chunk_start: i64) -> ByteStream {
+        read_chunks(
+            self.ton.clone(),
+            backend_key.to_string(),
+            range,
+            chunk_start,
+        )
     }
-    drop(buf);
 
-    let hasher = hasher.finalize();
+    async fn delete(&self, backend_key: &str) -> Result<(), S3Error> {
+        // ton has no way to delete a file, so its data stays there unreferenced
+        tracing::debug!("Not deleting {} from ton, which has no delete", backend_key);
+        Ok(())
+    }
 
-    let finalize_chunk_res = client
-        .post("http://localhost:4000/v1/upload/finalize")
-        .query(&[("token", &session.token)])
-        .json(&UploadFinalizeRequest {
-            name: "sagisawa.bin".to_string(),
-            md5: hex::encode(&hasher),
-        })
-        .send()
-        .await;
-
-    let finalize_chunk_res = match finalize_chunk_res {
-        Ok(v) => {
-            if !v.status().is_success() {
-                let status = v.status();
-                let v = v.text().await.unwrap_or_default();
-                tracing::error!("Failed to finish upload: {}, {}", status, v);
-                return Err(S3Error::InternalError.into_response());
+    async fn health(&self) -> bool {
+        // any HTTP answer means a server is up, one of them is enough
+        let mut healthy = false;
+        for endpoint in &self.ton.endpoints.endpoints {
+            match self.ton.client.get(&endpoint.base_url).send().await {
+                Ok(_) => healthy = true,
+                Err(e) => {
+                    endpoint.mark_failed();
+                    tracing::warn!("ton at {} is not reachable: {:?}", endpoint.base_url, e);
+                }
             }
-            v
-        }
-        Err(e) => {
-            tracing::error!("Failed to finish upload: {:?}", e);
-            return Err(S3Error::InternalError.into_response());
-        }
-    };
-
-    let finalize_chunk_res = finalize_chunk_res.json::<UploadFinalizeResponse>().await;
-
-    match finalize_chunk_res {
-        Ok(v) => Ok(Some(UploadResult {
-            r#ref: v.r#ref,
-            md5: hasher.into(),
-            size: offset,
-            chunks: all_chunks,
-        })),
-        Err(e) => {
-            tracing::error!("Failed to parse session finish response: {:?}", e);
-            return Err(S3Error::InternalError.into_response());
         }
+        healthy
     }
 }
@@ -0,0 +1,105 @@
+use std::{future::Future, time::Duration};
+
+use crate::s3serv::error::S3Error;
+
+/// How often and how patiently a failing request to ton is sent again.
+pub struct RetryPolicy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 4,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter, so that retries of many requests failing
+    /// at once are spread out.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(1 << retry.min(16))
+            .min(self.max_delay);
+        cap.mul_f64(fastrand::f64())
+    }
+}
+
+/// Why an attempt failed.
+pub enum Failure {
+    /// A connection problem, a timeout or a server error, which may not happen again.
+    Transient,
+    /// Sending the same request again gives the same answer.
+    Permanent,
+}
+
+/// Runs `attempt` until it succeeds, fails permanently or runs out of retries.
+pub async fn with_retry<T, F, Fut>(
+    retry: &RetryPolicy,
+    what: &str,
+    mut attempt: F,
+) -> Result<T, S3Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    for i in 0..=retry.retries {
+        if i > 0 {
+            let delay = retry.delay(i - 1);
+            tracing::info!(
+                "Retrying to {} in {:?} ({}/{})",
+                what,
+                delay,
+                i,
+                retry.retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+        match attempt().await {
+            Ok(v) => return Ok(v),
+            Err(Failure::Transient) => continue,
+            Err(Failure::Permanent) => return Err(S3Error::InternalError),
+        }
+    }
+    tracing::error!(
+        "Failed to {}: giving up after {} attempts",
+        what,
+        retry.retries + 1
+    );
+    Err(S3Error::InternalError)
+}
+
+/// Sorts a response into success or the kind of failure, logging failures.
+pub async fn check_response(
+    res: reqwest::Result<reqwest::Response>,
+    what: &str,
+    base_url: &str,
+) -> Result<reqwest::Response, Failure> {
+    let res = match res {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Failed to {} at {}: {:?}", what, base_url, e);
+            return Err(Failure::Transient);
+        }
+    };
+
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let text = res.text().await.unwrap_or_default();
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        tracing::warn!("Failed to {} at {}: {}, {}", what, base_url, status, text);
+        Err(Failure::Transient)
+    } else {
+        tracing::error!("Failed to {} at {}: {}, {}", what, base_url, status, text);
+        Err(Failure::Permanent)
+    }
+}
@@ -0,0 +1,2 @@
+pub mod drivers;
+pub mod s3serv;
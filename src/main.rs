@@ -1,9 +1,7 @@
+use sagisawa::{drivers, s3serv};
 use sqlx::postgres::PgPoolOptions;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod drivers;
-mod s3serv;
-
 fn init_registry() {
     let registry = tracing_subscriber::registry().with(
         tracing_subscriber::filter::EnvFilter::try_from_default_env()
@@ -28,5 +26,9 @@ async fn main() {
         .await
         .expect("Failed to create pool.");
 
-    s3serv::start_serv(pool).await;
+    let drivers = drivers::Drivers::from_env();
+    drivers.check_health().await;
+
+    let addr = std::env::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
+    s3serv::start_serv(pool, drivers, &addr).await;
 }
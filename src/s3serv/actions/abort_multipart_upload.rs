@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+
+use super::file_data;
+use crate::{drivers::Drivers, s3serv::error::S3Error};
+
+#[tracing::instrument(skip(pool, drivers))]
+pub async fn abort_multipart_upload(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+) -> Response {
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT multipart_uploads.id
+        FROM multipart_uploads
+            JOIN buckets ON buckets.id = multipart_uploads.bucket_id
+        WHERE
+            buckets.name = $1
+            AND multipart_uploads.key = $2
+            AND multipart_uploads.upload_id = $3
+        FOR UPDATE OF multipart_uploads
+    "#,
+        bucket,
+        key,
+        upload_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let multipart_upload_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchUpload.into_response();
+            }
+            tracing::error!("Failed to fetch multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let parts = sqlx::query!(
+        "SELECT id FROM multipart_upload_parts WHERE multipart_upload_id = $1",
+        multipart_upload_id
+    )
+    .fetch_all(&mut *tx)
+    .await;
+
+    let parts = match parts {
+        Ok(v) => v.into_iter().map(|v| v.id).collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::error!("Failed to fetch multipart upload parts: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let mut orphans = Vec::new();
+    if let Err(e) = file_data::delete_multipart_upload_parts(&mut tx, &parts, &mut orphans).await {
+        return e;
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM multipart_uploads WHERE id = $1",
+        multipart_upload_id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete multipart upload: {:?}", e);
+        return S3Error::InternalError.into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,114 @@
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::s3serv::error::S3Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, strum::EnumString, strum::IntoStaticStr)]
+pub enum Versioning {
+    Unversioned,
+    Enabled,
+    Suspended,
+}
+
+impl Versioning {
+    /// Parses the value of `buckets.versioning`.
+    pub fn from_db(value: &str) -> Result<Self, S3Error> {
+        value.parse().map_err(|_| {
+            tracing::error!("Unknown bucket versioning state: {}", value);
+            S3Error::InternalError
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct VersioningConfigurationRequest {
+    #[serde(rename = "Status")]
+    status: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename = "VersioningConfiguration")]
+struct VersioningConfiguration {
+    #[serde(rename = "Status", skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_bucket_versioning(pool: PgPool, bucket: String) -> Response {
+    let result = sqlx::query!(
+        "SELECT versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
+    )
+    .fetch_one(&pool)
+    .await;
+
+    let versioning = match result {
+        Ok(v) => v.versioning,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = VersioningConfiguration {
+        status: match versioning {
+            Versioning::Unversioned => None,
+            v => Some(v.into()),
+        },
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
+
+#[tracing::instrument(skip(pool, body))]
+pub async fn put_bucket_versioning(pool: PgPool, bucket: String, body: String) -> Response {
+    let request = match quick_xml::de::from_str::<VersioningConfigurationRequest>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Failed to parse VersioningConfiguration: {:?}", e);
+            return S3Error::MalformedXML.into_response();
+        }
+    };
+
+    // a bucket can never go back to the unversioned state
+    let versioning = match request.status.as_deref() {
+        Some("Enabled") => Versioning::Enabled,
+        Some("Suspended") => Versioning::Suspended,
+        _ => return S3Error::MalformedXML.into_response(),
+    };
+
+    let versioning: &'static str = versioning.into();
+    let result = sqlx::query!(
+        "UPDATE buckets SET versioning = $1 WHERE name = $2",
+        versioning,
+        bucket
+    )
+    .execute(&pool)
+    .await;
+
+    match result {
+        Ok(res) => {
+            if res.rows_affected() == 0 {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            axum::http::StatusCode::OK.into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to update bucket versioning: {:?}", e);
+            S3Error::InternalError.into_response()
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use axum::response::{IntoResponse, Response};
+use md5::Digest;
+use serde::Serialize;
+use sqlx::{types::Json, PgPool};
+
+use super::{
+    bucket_versioning::Versioning,
+    file_data, file_versions,
+    object_metadata::{ObjectMetadata, UserMetadata},
+};
+use crate::{
+    drivers::Drivers,
+    s3serv::{error::S3Error, etag::format_etag},
+};
+
+/// S3 rejects every part but the last one below this size.
+const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+struct CompleteMultipartUpload {
+    #[serde(rename = "Part", default)]
+    parts: Vec<CompletedPart>,
+}
+
+#[derive(serde::Deserialize)]
+struct CompletedPart {
+    #[serde(rename = "PartNumber")]
+    part_number: i32,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+#[derive(serde::Serialize)]
+struct CompleteMultipartUploadResult {
+    #[serde(rename = "Location")]
+    location: String,
+    #[serde(rename = "Bucket")]
+    bucket: String,
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+#[tracing::instrument(skip(pool, drivers, body))]
+pub async fn complete_multipart_upload(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    body: String,
+) -> Response {
+    let request = match quick_xml::de::from_str::<CompleteMultipartUpload>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Failed to parse CompleteMultipartUpload: {:?}", e);
+            return S3Error::MalformedXML.into_response();
+        }
+    };
+
+    if request.parts.is_empty() {
+        return S3Error::MalformedXML.into_response();
+    }
+
+    if request
+        .parts
+        .windows(2)
+        .any(|w| w[0].part_number >= w[1].part_number)
+    {
+        return S3Error::InvalidPartOrder.into_response();
+    }
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            multipart_uploads.id, multipart_uploads.bucket_id, buckets.versioning,
+            multipart_uploads.content_type, multipart_uploads.content_encoding,
+            multipart_uploads.content_disposition, multipart_uploads.content_language,
+            multipart_uploads.cache_control, multipart_uploads.expires,
+            multipart_uploads.user_metadata AS "user_metadata: Json<UserMetadata>"
+        FROM multipart_uploads
+            JOIN buckets ON buckets.id = multipart_uploads.bucket_id
+        WHERE
+            buckets.name = $1
+            AND multipart_uploads.key = $2
+            AND multipart_uploads.upload_id = $3
+        FOR UPDATE OF multipart_uploads
+    "#,
+        bucket,
+        key,
+        upload_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let upload = match result {
+        Ok(v) => v,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchUpload.into_response();
+            }
+            tracing::error!("Failed to fetch multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let versioning = match Versioning::from_db(&upload.versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let uploaded_parts = sqlx::query!(
+        "SELECT id, part_number, size, md5 FROM multipart_upload_parts WHERE multipart_upload_id = $1",
+        upload.id
+    )
+    .fetch_all(&mut *tx)
+    .await;
+
+    let uploaded_parts = match uploaded_parts {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to fetch multipart upload parts: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let mut parts = Vec::with_capacity(request.parts.len());
+    for (i, requested) in request.parts.iter().enumerate() {
+        let Some(part) = uploaded_parts
+            .iter()
+            .find(|p| p.part_number == requested.part_number)
+        else {
+            return S3Error::InvalidPart.into_response();
+        };
+        if requested.etag.trim().trim_matches('"') != hex::encode(&part.md5) {
+            return S3Error::InvalidPart.into_response();
+        }
+        if i + 1 < request.parts.len() && part.size < MIN_PART_SIZE {
+            return S3Error::EntityTooSmall.into_response();
+        }
+        parts.push(part);
+    }
+
+    let mut md5_hasher = md5::Md5::new();
+    for part in &parts {
+        md5_hasher.update(&part.md5);
+    }
+    let md5 = md5_hasher.finalize().to_vec();
+    let size = parts.iter().map(|p| p.size).sum::<i64>();
+    let parts_count = parts.len() as i32;
+
+    let data_id = sqlx::query!(
+        "INSERT INTO file_data(size, md5, multipart_parts_count) VALUES($1, $2, $3) RETURNING id",
+        size,
+        md5,
+        parts_count
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let data_id = match data_id {
+        Ok(v) => v.id,
+        Err(e) => {
+            tracing::error!("Failed to insert file data: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let mut offset = 0i64;
+    for part in &parts {
+        let result = sqlx::query!(
+            r#"
+            UPDATE file_data_parts
+            SET
+                file_data_id = $1,
+                multipart_upload_part_id = NULL,
+                range = int8range(lower(range) + $2, upper(range) + $2)
+            WHERE multipart_upload_part_id = $3
+        "#,
+            data_id,
+            offset,
+            part.id
+        )
+        .execute(&mut *tx)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!("Failed to attach file data part: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+
+        offset += part.size;
+    }
+
+    // parts which were uploaded but not listed are discarded
+    let unused_parts = uploaded_parts
+        .iter()
+        .filter(|p| !parts.iter().any(|used| used.id == p.id))
+        .map(|p| p.id)
+        .collect::<Vec<_>>();
+
+    let mut orphans = Vec::new();
+    if let Err(e) =
+        file_data::delete_multipart_upload_parts(&mut tx, &unused_parts, &mut orphans).await
+    {
+        return e;
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM multipart_upload_parts WHERE multipart_upload_id = $1",
+        upload.id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete multipart upload parts: {:?}", e);
+        return S3Error::InternalError.into_response();
+    }
+
+    let result = sqlx::query!("DELETE FROM multipart_uploads WHERE id = $1", upload.id)
+        .execute(&mut *tx)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete multipart upload: {:?}", e);
+        return S3Error::InternalError.into_response();
+    }
+
+    let metadata = ObjectMetadata {
+        content_type: upload.content_type,
+        content_encoding: upload.content_encoding,
+        content_disposition: upload.content_disposition,
+        content_language: upload.content_language,
+        cache_control: upload.cache_control,
+        expires: upload.expires,
+        user: upload.user_metadata.map(|v| v.0).unwrap_or_default(),
+    };
+
+    let version_id = match file_versions::create_version(
+        &mut tx,
+        upload.bucket_id,
+        &key,
+        Some(data_id),
+        false,
+        versioning,
+        &metadata,
+        None,
+        &mut orphans,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let result = CompleteMultipartUploadResult {
+        location: format!("/{}/{}", bucket, key),
+        bucket,
+        key,
+        etag: format_etag(&md5, Some(parts_count)),
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    let mut res = ([("Content-Type", "application/xml")], buffer).into_response();
+    if versioning == Versioning::Enabled {
+        res.headers_mut()
+            .insert("x-amz-version-id", version_id.to_string().parse().unwrap());
+    }
+    res
+}
@@ -0,0 +1,115 @@
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+
+use crate::s3serv::error::S3Error;
+
+/// `If-Match`, `If-None-Match`, `If-Modified-Since` and `If-Unmodified-Since`,
+/// or their `x-amz-copy-source-if-*` counterparts.
+#[derive(Default, Debug)]
+pub struct Preconditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+    if_unmodified_since: Option<DateTime<Utc>>,
+}
+
+/// Outcome of evaluating [`Preconditions`] against an object.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Evaluation {
+    Passed,
+    /// `If-None-Match` or `If-Modified-Since` failed: 304 for GET/HEAD.
+    NotModified,
+    /// `If-Match` or `If-Unmodified-Since` failed: 412.
+    Failed,
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Invalid dates are ignored, as RFC 9110 says.
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|v| v.with_timezone(&Utc))
+}
+
+/// Whether an `If-Match`/`If-None-Match` list (or `*`) contains `etag`.
+///
+/// Our ETags are always strong, so `W/` is ignored. Quotes are optional, S3 accepts both.
+fn etag_matches(list: &str, etag: &str) -> bool {
+    let etag = etag.trim_matches('"');
+    list.split(',').map(str::trim).any(|v| {
+        let v = v.strip_prefix("W/").unwrap_or(v);
+        v == "*" || v.trim_matches('"') == etag
+    })
+}
+
+impl Preconditions {
+    /// Reads the conditional headers, `prefix` being e.g. `x-amz-copy-source-` for CopyObject.
+    pub fn from_headers(headers: &HeaderMap, prefix: &str) -> Self {
+        let get = |name: &str| header_str(headers, &format!("{}{}", prefix, name));
+        Preconditions {
+            if_match: get("if-match").map(str::to_string),
+            if_none_match: get("if-none-match").map(str::to_string),
+            if_modified_since: get("if-modified-since").and_then(parse_http_date),
+            if_unmodified_since: get("if-unmodified-since").and_then(parse_http_date),
+        }
+    }
+
+    /// Evaluates the conditions in the order of RFC 9110 section 13.2.2: a date condition
+    /// is only looked at when the matching ETag condition is absent.
+    pub fn evaluate(&self, etag: &str, last_modified: &DateTime<Utc>) -> Evaluation {
+        // HTTP dates have a resolution of one second
+        let last_modified = last_modified.timestamp();
+
+        match (&self.if_match, &self.if_unmodified_since) {
+            (Some(list), _) if !etag_matches(list, etag) => return Evaluation::Failed,
+            (None, Some(since)) if last_modified > since.timestamp() => return Evaluation::Failed,
+            _ => (),
+        }
+
+        match (&self.if_none_match, &self.if_modified_since) {
+            (Some(list), _) if etag_matches(list, etag) => Evaluation::NotModified,
+            (None, Some(since)) if last_modified <= since.timestamp() => Evaluation::NotModified,
+            _ => Evaluation::Passed,
+        }
+    }
+}
+
+/// `If-None-Match: *` or `If-Match` on PutObject.
+#[derive(Debug)]
+pub enum WriteCondition {
+    /// Only create the object, never overwrite it.
+    IfNoneMatch,
+    /// Only overwrite the object while it still has one of these ETags.
+    IfMatch(String),
+}
+
+impl WriteCondition {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, S3Error> {
+        match (
+            header_str(headers, "if-none-match"),
+            header_str(headers, "if-match"),
+        ) {
+            (None, None) => Ok(None),
+            (Some("*"), None) => Ok(Some(WriteCondition::IfNoneMatch)),
+            // like S3, only the wildcard is supported
+            (Some(_), None) => Err(S3Error::NotImplemented),
+            (None, Some(list)) => Ok(Some(WriteCondition::IfMatch(list.to_string()))),
+            (Some(_), Some(_)) => Err(S3Error::InvalidArgument),
+        }
+    }
+
+    /// Checks the condition against the ETag of the current object, `None` when there is
+    /// no object (or the current version is a delete marker).
+    pub fn check(&self, current_etag: Option<&str>) -> Result<(), S3Error> {
+        match (self, current_etag) {
+            (WriteCondition::IfNoneMatch, None) => Ok(()),
+            (WriteCondition::IfNoneMatch, Some(_)) => Err(S3Error::PreconditionFailed),
+            (WriteCondition::IfMatch(_), None) => Err(S3Error::NoSuchKey),
+            (WriteCondition::IfMatch(list), Some(etag)) if etag_matches(list, etag) => Ok(()),
+            (WriteCondition::IfMatch(_), Some(_)) => Err(S3Error::PreconditionFailed),
+        }
+    }
+}
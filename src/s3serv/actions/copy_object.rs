@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use chrono::SecondsFormat;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use super::{
+    bucket_versioning::Versioning,
+    conditions::{Evaluation, Preconditions},
+    file_data, file_versions,
+    get_object::{self, ObjectInfo},
+    object_metadata::ObjectMetadata,
+};
+use crate::{drivers::Drivers, s3serv::error::S3Error};
+
+#[derive(serde::Serialize)]
+struct CopyObjectResult {
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+/// Where `x-amz-copy-source` points to.
+pub struct CopySource {
+    pub bucket: String,
+    pub key: String,
+    pub version_id: Option<String>,
+}
+
+impl CopySource {
+    /// Parses `[/]<bucket>/<url-encoded key>[?versionId=<version id>]`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let Some(value) = headers
+            .get("x-amz-copy-source")
+            .and_then(|v| v.to_str().ok())
+        else {
+            return Err(S3Error::InvalidArgument);
+        };
+        let (path, query) = match value.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (value, None),
+        };
+        let version_id = match query.map(|v| v.strip_prefix("versionId=")) {
+            None => None,
+            Some(Some(v)) => Some(percent_decode_str(v).decode_utf8_lossy().into_owned()),
+            Some(None) => return Err(S3Error::InvalidArgument),
+        };
+        let Ok(path) = percent_decode_str(path.trim_start_matches('/')).decode_utf8() else {
+            return Err(S3Error::InvalidArgument);
+        };
+        match path.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(CopySource {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                version_id,
+            }),
+            _ => Err(S3Error::InvalidArgument),
+        }
+    }
+
+    /// Looks up the source object and checks the `x-amz-copy-source-if-*` conditions.
+    pub async fn find_object(
+        &self,
+        pool: &PgPool,
+        headers: &HeaderMap,
+    ) -> Result<ObjectInfo, Response> {
+        let object =
+            get_object::find_object(pool, &self.bucket, &self.key, self.version_id.as_deref())
+                .await?;
+
+        let preconditions = Preconditions::from_headers(headers, "x-amz-copy-source-");
+        match preconditions.evaluate(&object.etag(), &object.last_modified) {
+            Evaluation::Passed => Ok(object),
+            Evaluation::NotModified | Evaluation::Failed => {
+                Err(S3Error::PreconditionFailed.into_response())
+            }
+        }
+    }
+}
+
+enum MetadataDirective {
+    Copy,
+    Replace,
+}
+
+fn parse_metadata_directive(headers: &HeaderMap) -> Result<MetadataDirective, S3Error> {
+    match headers
+        .get("x-amz-metadata-directive")
+        .map(|v| v.as_bytes())
+    {
+        None | Some(b"COPY") => Ok(MetadataDirective::Copy),
+        Some(b"REPLACE") => Ok(MetadataDirective::Replace),
+        Some(_) => Err(S3Error::InvalidArgument),
+    }
+}
+
+/// Copies an object without touching its data: the new version shares the `file_data` row.
+#[tracing::instrument(skip(pool, drivers, headers))]
+pub async fn copy_object(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    headers: HeaderMap,
+) -> Response {
+    let source = match CopySource::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let directive = match parse_metadata_directive(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    if let MetadataDirective::Copy = directive {
+        // S3 refuses copies that would not change anything
+        if source.bucket == bucket && source.key == key && source.version_id.is_none() {
+            return S3Error::InvalidRequest.into_response();
+        }
+    }
+
+    let object = match source.find_object(&pool, &headers).await {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let etag = object.etag();
+
+    let metadata = match directive {
+        MetadataDirective::Copy => object.metadata,
+        MetadataDirective::Replace => match ObjectMetadata::from_headers(&headers) {
+            Ok(v) => v,
+            Err(e) => return e.into_response(),
+        },
+    };
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        "SELECT id, versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let (bucket_id, versioning) = match result {
+        Ok(v) => (v.id, v.versioning),
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    // the source may have been deleted since it was looked up, and its data released
+    if let Some(data_id) = object.data_id {
+        let source = sqlx::query!("SELECT id FROM file_data WHERE id = $1 FOR SHARE", data_id)
+            .fetch_optional(&mut *tx)
+            .await;
+
+        match source {
+            Ok(Some(_)) => (),
+            Ok(None) => return S3Error::NoSuchKey.into_response(),
+            Err(e) => {
+                tracing::error!("Failed to lock file data: {:?}", e);
+                return S3Error::InternalError.into_response();
+            }
+        }
+    }
+
+    let mut orphans = Vec::new();
+    let version_id = match file_versions::create_version(
+        &mut tx,
+        bucket_id,
+        &key,
+        object.data_id,
+        false,
+        versioning,
+        &metadata,
+        None,
+        &mut orphans,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let created_at = sqlx::query!(
+        "SELECT created_at FROM file_versions WHERE id = $1",
+        version_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let created_at = match created_at {
+        Ok(v) => v.created_at,
+        Err(e) => {
+            tracing::error!("Failed to fetch file version: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let result = CopyObjectResult {
+        last_modified: created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        etag,
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    let mut res = ([("Content-Type", "application/xml")], buffer).into_response();
+    if versioning == Versioning::Enabled {
+        res.headers_mut()
+            .insert("x-amz-version-id", version_id.to_string().parse().unwrap());
+    }
+    if let Some(source_version_id) = &object.version_id {
+        res.headers_mut().insert(
+            "x-amz-copy-source-version-id",
+            source_version_id.parse().unwrap(),
+        );
+    }
+    res
+}
@@ -0,0 +1,90 @@
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use super::object_metadata::ObjectMetadata;
+use crate::s3serv::error::S3Error;
+
+#[derive(serde::Serialize)]
+struct InitiateMultipartUploadResult {
+    #[serde(rename = "Bucket")]
+    bucket: String,
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "UploadId")]
+    upload_id: String,
+}
+
+#[tracing::instrument(skip(pool, headers))]
+pub async fn create_multipart_upload(
+    pool: PgPool,
+    bucket: String,
+    key: String,
+    headers: HeaderMap,
+) -> Response {
+    let metadata = match ObjectMetadata::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
+        .fetch_one(&pool)
+        .await;
+
+    let bucket_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO multipart_uploads(
+            bucket_id, key,
+            content_type, content_encoding, content_disposition, content_language,
+            cache_control, expires, user_metadata
+        )
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING upload_id
+    "#,
+        bucket_id,
+        key,
+        metadata.content_type,
+        metadata.content_encoding,
+        metadata.content_disposition,
+        metadata.content_language,
+        metadata.cache_control,
+        metadata.expires,
+        metadata.user_json() as _
+    )
+    .fetch_one(&pool)
+    .await;
+
+    let upload_id = match result {
+        Ok(v) => v.upload_id,
+        Err(e) => {
+            tracing::error!("Failed to create multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = InitiateMultipartUploadResult {
+        bucket,
+        key,
+        upload_id,
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
@@ -16,11 +16,11 @@ pub async fn delete_bucket(pool: PgPool, bucket: String) -> Response {
             if res.rows_affected() == 0 {
                 return S3Error::NoSuchBucket.into_response();
             }
-            return StatusCode::NO_CONTENT.into_response();
+            StatusCode::NO_CONTENT.into_response()
         }
         Err(e) => {
             tracing::error!("failed to delete bucket {}: {:?}", bucket, e);
-            return S3Error::InternalError.into_response();
+            S3Error::InternalError.into_response()
         }
-    };
+    }
 }
@@ -0,0 +1,316 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgPool, PgTransaction};
+
+use super::{
+    bucket_versioning::Versioning,
+    file_data::{self, BackendObject},
+    file_versions,
+    object_metadata::ObjectMetadata,
+};
+use crate::{drivers::Drivers, s3serv::error::S3Error};
+
+pub struct DeletedObject {
+    pub delete_marker: bool,
+    pub version_id: Option<String>,
+}
+
+/// Deletes `key` inside an already started transaction.
+///
+/// Versioned (and suspended) buckets get a delete marker on top of the existing versions,
+/// unversioned buckets lose the object for good. Data left unreferenced goes to `orphans`.
+pub async fn delete_object_in_tx(
+    tx: &mut PgTransaction<'_>,
+    bucket_id: i32,
+    versioning: Versioning,
+    key: &str,
+    orphans: &mut Vec<BackendObject>,
+) -> Result<DeletedObject, Response> {
+    if versioning != Versioning::Unversioned {
+        let version_id = file_versions::create_version(
+            tx,
+            bucket_id,
+            key,
+            None,
+            true,
+            versioning,
+            &ObjectMetadata::default(),
+            None,
+            orphans,
+        )
+        .await?;
+
+        return Ok(DeletedObject {
+            delete_marker: true,
+            version_id: Some(file_versions::format_version_id(
+                version_id,
+                versioning != Versioning::Enabled,
+            )),
+        });
+    }
+
+    let file = sqlx::query!(
+        "SELECT id FROM files WHERE bucket_id = $1 AND key = $2 LIMIT 1 FOR UPDATE",
+        bucket_id,
+        key
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    let file_id = match file {
+        Ok(Some(v)) => v.id,
+        Ok(None) => {
+            return Ok(DeletedObject {
+                delete_marker: false,
+                version_id: None,
+            })
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch file: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let result = sqlx::query!("SET CONSTRAINTS ALL DEFERRED")
+        .execute(&mut **tx)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to set constraints deferred: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM file_versions WHERE file_id = $1 RETURNING file_data_id",
+        file_id
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    let data_ids = match result {
+        Ok(v) => v
+            .into_iter()
+            .filter_map(|v| v.file_data_id)
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::error!("Failed to delete file versions: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let result = sqlx::query!("DELETE FROM files WHERE id = $1", file_id)
+        .execute(&mut **tx)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete file: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    file_data::release_file_data(tx, &data_ids, orphans).await?;
+
+    Ok(DeletedObject {
+        delete_marker: false,
+        version_id: None,
+    })
+}
+
+/// Permanently deletes a single version of `key` inside an already started transaction.
+///
+/// `version_id` is the parsed id (see [`file_versions::parse_version_id`]). Deleting a
+/// version that does not exist is not an error. If the current version goes away, the
+/// newest remaining one takes its place. Data left unreferenced goes to `orphans`.
+pub async fn delete_version_in_tx(
+    tx: &mut PgTransaction<'_>,
+    bucket_id: i32,
+    key: &str,
+    version_id: Option<i32>,
+    orphans: &mut Vec<BackendObject>,
+) -> Result<DeletedObject, Response> {
+    let formatted_version_id = version_id.map_or_else(|| "null".to_string(), |v| v.to_string());
+
+    let file = sqlx::query!(
+        "SELECT id, current_version FROM files WHERE bucket_id = $1 AND key = $2 LIMIT 1 FOR UPDATE",
+        bucket_id,
+        key
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    let (file_id, current_version) = match file {
+        Ok(Some(v)) => (v.id, v.current_version),
+        Ok(None) => {
+            return Ok(DeletedObject {
+                delete_marker: false,
+                version_id: Some(formatted_version_id),
+            })
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch file: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let result = sqlx::query!("SET CONSTRAINTS ALL DEFERRED")
+        .execute(&mut **tx)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to set constraints deferred: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM file_versions
+        WHERE file_id = $1 AND (id = $2 OR ($2 IS NULL AND is_null_version))
+        RETURNING id, is_delete_marker, file_data_id
+    "#,
+        file_id,
+        version_id
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    let deleted = match deleted {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            return Ok(DeletedObject {
+                delete_marker: false,
+                version_id: Some(formatted_version_id),
+            })
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete file version: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    if deleted.id == current_version {
+        let latest = sqlx::query!(
+            "SELECT id, is_delete_marker FROM file_versions WHERE file_id = $1 ORDER BY id DESC LIMIT 1",
+            file_id
+        )
+        .fetch_optional(&mut **tx)
+        .await;
+
+        let result = match latest {
+            Ok(Some(latest)) => {
+                sqlx::query!(
+                    "UPDATE files SET current_version = $1, current_version_is_delete_marker = $2 WHERE id = $3",
+                    latest.id,
+                    latest.is_delete_marker,
+                    file_id
+                )
+                .execute(&mut **tx)
+                .await
+            }
+            Ok(None) => {
+                sqlx::query!("DELETE FROM files WHERE id = $1", file_id)
+                    .execute(&mut **tx)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            tracing::error!("Failed to update current version: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    }
+
+    if let Some(data_id) = deleted.file_data_id {
+        file_data::release_file_data(tx, &[data_id], orphans).await?;
+    }
+
+    Ok(DeletedObject {
+        delete_marker: deleted.is_delete_marker,
+        version_id: Some(formatted_version_id),
+    })
+}
+
+#[tracing::instrument(skip(pool, drivers))]
+pub async fn delete_object(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    version_id: Option<String>,
+) -> Response {
+    let version_id = match version_id.map(|v| file_versions::parse_version_id(&v)) {
+        None => None,
+        Some(Ok(v)) => Some(v),
+        Some(Err(e)) => return e.into_response(),
+    };
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        "SELECT id, versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let (bucket_id, versioning) = match result {
+        Ok(v) => (v.id, v.versioning),
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut orphans = Vec::new();
+    let deleted = match version_id {
+        Some(version_id) => {
+            delete_version_in_tx(&mut tx, bucket_id, &key, version_id, &mut orphans).await
+        }
+        None => delete_object_in_tx(&mut tx, bucket_id, versioning, &key, &mut orphans).await,
+    };
+
+    let deleted = match deleted {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let mut res = StatusCode::NO_CONTENT.into_response();
+    if deleted.delete_marker {
+        res.headers_mut()
+            .insert("x-amz-delete-marker", "true".parse().unwrap());
+    }
+    if let Some(version_id) = deleted.version_id {
+        res.headers_mut()
+            .insert("x-amz-version-id", version_id.parse().unwrap());
+    }
+    res
+}
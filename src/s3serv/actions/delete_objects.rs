@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use super::{
+    bucket_versioning::Versioning,
+    delete_object::{delete_object_in_tx, delete_version_in_tx},
+    file_data,
+    file_versions::parse_version_id,
+};
+use crate::{drivers::Drivers, s3serv::error::S3Error};
+
+const MAX_KEYS: usize = 1000;
+const MAX_KEY_LENGTH: usize = 1024;
+
+#[derive(serde::Deserialize)]
+struct Delete {
+    #[serde(rename = "Quiet", default)]
+    quiet: bool,
+    #[serde(rename = "Object", default)]
+    objects: Vec<ObjectIdentifier>,
+}
+
+#[derive(serde::Deserialize)]
+struct ObjectIdentifier {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "VersionId")]
+    version_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct DeleteResult {
+    #[serde(rename = "Deleted")]
+    deleted: Vec<Deleted>,
+    #[serde(rename = "Error")]
+    errors: Vec<Error>,
+}
+
+#[derive(serde::Serialize)]
+struct Deleted {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "VersionId", skip_serializing_if = "Option::is_none")]
+    version_id: Option<String>,
+    #[serde(rename = "DeleteMarker", skip_serializing_if = "Option::is_none")]
+    delete_marker: Option<bool>,
+    #[serde(
+        rename = "DeleteMarkerVersionId",
+        skip_serializing_if = "Option::is_none"
+    )]
+    delete_marker_version_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Error {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "VersionId", skip_serializing_if = "Option::is_none")]
+    version_id: Option<String>,
+    #[serde(rename = "Code")]
+    code: &'static str,
+    #[serde(rename = "Message")]
+    message: &'static str,
+}
+
+impl Error {
+    fn new(object: ObjectIdentifier, error: S3Error) -> Self {
+        Error {
+            key: object.key,
+            version_id: object.version_id,
+            message: error.message(),
+            code: error.into(),
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool, drivers, body))]
+pub async fn delete_objects(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    body: String,
+) -> Response {
+    let request = match quick_xml::de::from_str::<Delete>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Failed to parse Delete: {:?}", e);
+            return S3Error::MalformedXML.into_response();
+        }
+    };
+
+    if request.objects.is_empty() || request.objects.len() > MAX_KEYS {
+        return S3Error::MalformedXML.into_response();
+    }
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        "SELECT id, versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let (bucket_id, versioning) = match result {
+        Ok(v) => (v.id, v.versioning),
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut result = DeleteResult {
+        deleted: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut orphans = Vec::new();
+
+    for object in request.objects {
+        if object.key.is_empty() {
+            result
+                .errors
+                .push(Error::new(object, S3Error::UserKeyMustBeSpecified));
+            continue;
+        }
+        if object.key.chars().count() > MAX_KEY_LENGTH {
+            result
+                .errors
+                .push(Error::new(object, S3Error::KeyTooLongError));
+            continue;
+        }
+
+        let deleted = match object.version_id.as_deref().map(parse_version_id) {
+            None => {
+                delete_object_in_tx(&mut tx, bucket_id, versioning, &object.key, &mut orphans).await
+            }
+            Some(Ok(version_id)) => {
+                delete_version_in_tx(&mut tx, bucket_id, &object.key, version_id, &mut orphans)
+                    .await
+            }
+            Some(Err(e)) => {
+                result.errors.push(Error::new(object, e));
+                continue;
+            }
+        };
+
+        let deleted = match deleted {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+
+        if request.quiet {
+            continue;
+        }
+
+        // for a versioned delete the version id is the one that was removed,
+        // otherwise it is the id of the delete marker that was just created
+        let (version_id, delete_marker_version_id) = match object.version_id {
+            Some(_) => (deleted.version_id, None),
+            None => (None, deleted.version_id.filter(|_| deleted.delete_marker)),
+        };
+
+        result.deleted.push(Deleted {
+            key: object.key,
+            version_id,
+            delete_marker: deleted.delete_marker.then_some(true),
+            delete_marker_version_id,
+        });
+    }
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
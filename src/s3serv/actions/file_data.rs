@@ -0,0 +1,412 @@
+use std::ops::Range;
+
+use axum::response::{IntoResponse, Response};
+use sqlx::{postgres::types::PgRange, PgTransaction};
+
+use crate::{
+    drivers::{Drivers, UploadResult},
+    s3serv::error::S3Error,
+};
+
+/// An object of a storage driver which no `file_data_parts` row refers to anymore.
+///
+/// It is deleted from the driver only once the transaction dropping the last reference
+/// has committed, see [`delete_backend_objects`].
+pub struct BackendObject {
+    pub driver: String,
+    pub backend_key: String,
+}
+
+/// Records an object uploaded with `driver` as a `file_data_parts` row (plus its chunk info).
+///
+/// Parts of an in-progress multipart upload have no `file_data_id` yet and are
+/// attached to their `multipart_upload_parts` row instead.
+pub async fn insert_data_part(
+    tx: &mut PgTransaction<'_>,
+    driver: &str,
+    file_data_id: Option<i32>,
+    multipart_upload_part_id: Option<i32>,
+    result: UploadResult,
+) -> Result<i32, Response> {
+    let part_id = sqlx::query!(
+        r#"
+        INSERT INTO file_data_parts(file_data_id, multipart_upload_part_id, driver, backend_key, range)
+        VALUES($1, $2, $3, $4, $5)
+        RETURNING id
+    "#,
+        file_data_id,
+        multipart_upload_part_id,
+        driver,
+        result.r#ref,
+        PgRange::from(0..(result.size as i64))
+    )
+    .fetch_one(&mut **tx)
+    .await;
+
+    let part_id = match part_id {
+        Ok(rec) => rec.id,
+        Err(e) => {
+            tracing::error!("Failed to insert file data part: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let mut builder = sqlx::QueryBuilder::new(
+        "INSERT INTO file_data_part_chunk_info (part_id, range, md5, sha256) ",
+    );
+
+    builder.push_values(result.chunks, |mut b, chunk| {
+        b.push_bind(part_id)
+            .push_bind(PgRange::from(chunk.range.clone()))
+            .push_bind(chunk.md5)
+            .push_bind(chunk.sha256);
+    });
+
+    let insert_chunk = builder.build().execute(&mut **tx).await;
+
+    if let Err(e) = insert_chunk {
+        tracing::error!("Failed to insert chunk info: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    Ok(part_id)
+}
+
+/// Makes a part of a multipart upload out of `range` of an existing `file_data`, by creating
+/// `file_data_parts` rows that point at sub-ranges of the source's backend objects.
+///
+/// The chunk info of the source is duplicated, so reads can still start on a chunk boundary.
+pub async fn insert_copied_data_parts(
+    tx: &mut PgTransaction<'_>,
+    multipart_upload_part_id: i32,
+    source_data_id: i32,
+    range: Range<i64>,
+) -> Result<(), Response> {
+    // keeps the source from being released while its backend objects are shared
+    let source = sqlx::query!(
+        "SELECT id FROM file_data WHERE id = $1 FOR SHARE",
+        source_data_id
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match source {
+        Ok(Some(_)) => (),
+        Ok(None) => return Err(S3Error::NoSuchKey.into_response()),
+        Err(e) => {
+            tracing::error!("Failed to lock file data: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    }
+
+    let source_parts = sqlx::query!(
+        r#"
+        SELECT id, lower(range) AS "start!", upper(range) AS "end!", backend_offset
+        FROM file_data_parts
+        WHERE file_data_id = $1 AND range && $2
+        ORDER BY lower(range)
+    "#,
+        source_data_id,
+        PgRange::from(range.clone())
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    let source_parts = match source_parts {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to fetch file data parts: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    for source in source_parts {
+        let start = range.start.max(source.start);
+        let end = range.end.min(source.end);
+        // where the copied bytes are in the backend object
+        let backend_offset = source.backend_offset + (start - source.start);
+
+        let part_id = sqlx::query!(
+            r#"
+            INSERT INTO file_data_parts(
+                multipart_upload_part_id, driver, backend_key, range, backend_offset,
+                encrypt_metadata, encrypt_bindata
+            )
+            SELECT $1, driver, backend_key, $2, $3, encrypt_metadata, encrypt_bindata
+            FROM file_data_parts
+            WHERE id = $4
+            RETURNING id
+        "#,
+            multipart_upload_part_id,
+            PgRange::from((start - range.start)..(end - range.start)),
+            backend_offset,
+            source.id
+        )
+        .fetch_one(&mut **tx)
+        .await;
+
+        let part_id = match part_id {
+            Ok(rec) => rec.id,
+            Err(e) => {
+                tracing::error!("Failed to insert file data part: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        };
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO file_data_part_chunk_info(part_id, range, md5, sha1, sha256)
+            SELECT $1, range, md5, sha1, sha256
+            FROM file_data_part_chunk_info
+            WHERE part_id = $2 AND range && $3
+        "#,
+            part_id,
+            source.id,
+            PgRange::from(backend_offset..backend_offset + (end - start))
+        )
+        .execute(&mut **tx)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!("Failed to copy chunk info: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes parts of a multipart upload together with the data parts staged for them.
+///
+/// Backend objects left without a reference are added to `orphans`.
+pub async fn delete_multipart_upload_parts(
+    tx: &mut PgTransaction<'_>,
+    multipart_upload_part_ids: &[i32],
+    orphans: &mut Vec<BackendObject>,
+) -> Result<(), Response> {
+    if multipart_upload_part_ids.is_empty() {
+        return Ok(());
+    }
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM file_data_part_chunk_info
+        WHERE part_id IN (
+            SELECT id FROM file_data_parts WHERE multipart_upload_part_id = ANY($1)
+        )
+    "#,
+        multipart_upload_part_ids
+    )
+    .execute(&mut **tx)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete chunk info: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    let deleted = sqlx::query!(
+        "DELETE FROM file_data_parts WHERE multipart_upload_part_id = ANY($1) RETURNING driver, backend_key",
+        multipart_upload_part_ids
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    let deleted = match deleted {
+        Ok(v) => v
+            .into_iter()
+            .map(|v| BackendObject {
+                driver: v.driver,
+                backend_key: v.backend_key,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failed to delete file data parts: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let result = sqlx::query!(
+        "DELETE FROM multipart_upload_parts WHERE id = ANY($1)",
+        multipart_upload_part_ids
+    )
+    .execute(&mut **tx)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete multipart upload parts: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    collect_unreferenced(tx, deleted, orphans).await
+}
+
+/// Deletes the `file_data` rows among `file_data_ids` which no version refers to anymore,
+/// with their data parts. Backend objects left without a reference are added to `orphans`.
+///
+/// Versions may share a `file_data` row (see CopyObject), so callers pass every row they
+/// dropped a reference to and this sorts out which ones are gone for good.
+pub async fn release_file_data(
+    tx: &mut PgTransaction<'_>,
+    file_data_ids: &[i32],
+    orphans: &mut Vec<BackendObject>,
+) -> Result<(), Response> {
+    if file_data_ids.is_empty() {
+        return Ok(());
+    }
+
+    // waits for transactions about to refer to them, whose versions are then seen below
+    let locked = sqlx::query!(
+        "SELECT id FROM file_data WHERE id = ANY($1) FOR UPDATE",
+        file_data_ids
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    if let Err(e) = locked {
+        tracing::error!("Failed to lock file data: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    let unreferenced = sqlx::query!(
+        r#"
+        SELECT id FROM file_data
+        WHERE
+            id = ANY($1)
+            AND NOT EXISTS (SELECT 1 FROM file_versions WHERE file_data_id = file_data.id)
+    "#,
+        file_data_ids
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    let unreferenced = match unreferenced {
+        Ok(v) => v.into_iter().map(|v| v.id).collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::error!("Failed to fetch unreferenced file data: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    if unreferenced.is_empty() {
+        return Ok(());
+    }
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM file_data_part_chunk_info
+        WHERE part_id IN (SELECT id FROM file_data_parts WHERE file_data_id = ANY($1))
+    "#,
+        &unreferenced
+    )
+    .execute(&mut **tx)
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete chunk info: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    let deleted = sqlx::query!(
+        "DELETE FROM file_data_parts WHERE file_data_id = ANY($1) RETURNING driver, backend_key",
+        &unreferenced
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    let deleted = match deleted {
+        Ok(v) => v
+            .into_iter()
+            .map(|v| BackendObject {
+                driver: v.driver,
+                backend_key: v.backend_key,
+            })
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failed to delete file data parts: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let result = sqlx::query!("DELETE FROM file_data WHERE id = ANY($1)", &unreferenced)
+        .execute(&mut **tx)
+        .await;
+
+    if let Err(e) = result {
+        tracing::error!("Failed to delete file data: {:?}", e);
+        return Err(S3Error::InternalError.into_response());
+    }
+
+    collect_unreferenced(tx, deleted, orphans).await
+}
+
+/// Adds the backend objects of just deleted data parts to `orphans` unless another data
+/// part still refers to them, as UploadPartCopy shares backend objects.
+async fn collect_unreferenced(
+    tx: &mut PgTransaction<'_>,
+    mut deleted: Vec<BackendObject>,
+    orphans: &mut Vec<BackendObject>,
+) -> Result<(), Response> {
+    deleted.sort_by(|a, b| (&a.driver, &a.backend_key).cmp(&(&b.driver, &b.backend_key)));
+    deleted.dedup_by(|a, b| a.driver == b.driver && a.backend_key == b.backend_key);
+    if deleted.is_empty() {
+        return Ok(());
+    }
+
+    let (drivers, backend_keys): (Vec<_>, Vec<_>) = deleted
+        .into_iter()
+        .map(|v| (v.driver, v.backend_key))
+        .unzip();
+    let unreferenced = sqlx::query!(
+        r#"
+        SELECT candidates.driver AS "driver!", candidates.backend_key AS "backend_key!"
+        FROM unnest($1::text[], $2::text[]) AS candidates(driver, backend_key)
+        WHERE NOT EXISTS (
+            SELECT 1 FROM file_data_parts
+            WHERE
+                file_data_parts.driver = candidates.driver
+                AND file_data_parts.backend_key = candidates.backend_key
+        )
+    "#,
+        &drivers,
+        &backend_keys
+    )
+    .fetch_all(&mut **tx)
+    .await;
+
+    match unreferenced {
+        Ok(v) => {
+            orphans.extend(v.into_iter().map(|v| BackendObject {
+                driver: v.driver,
+                backend_key: v.backend_key,
+            }));
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch unreferenced backend objects: {:?}", e);
+            Err(S3Error::InternalError.into_response())
+        }
+    }
+}
+
+/// Deletes backend objects nothing refers to anymore. Failures only leave garbage behind,
+/// so they are logged and otherwise ignored.
+pub async fn delete_backend_objects(drivers: &Drivers, orphans: Vec<BackendObject>) {
+    for orphan in orphans {
+        let Some(driver) = drivers.get(&orphan.driver) else {
+            tracing::warn!(
+                "Not deleting {} of unknown driver {}",
+                orphan.backend_key,
+                orphan.driver
+            );
+            continue;
+        };
+        if let Err(e) = driver.delete(&orphan.backend_key).await {
+            tracing::warn!(
+                "Failed to delete backend object {}: {}",
+                orphan.backend_key,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use axum::response::{IntoResponse, Response};
+use md5::Digest;
+use sqlx::PgTransaction;
+
+use super::{
+    bucket_versioning::Versioning,
+    conditions::WriteCondition,
+    file_data::{self, BackendObject},
+    object_metadata::ObjectMetadata,
+};
+use crate::s3serv::{error::S3Error, etag::format_etag};
+
+/// Formats a version id as exposed by the S3 API.
+pub fn format_version_id(id: i32, is_null_version: bool) -> String {
+    if is_null_version {
+        "null".to_string()
+    } else {
+        id.to_string()
+    }
+}
+
+/// Parses a version id given by a client. The "null" version is returned as `None`.
+pub fn parse_version_id(value: &str) -> Result<Option<i32>, S3Error> {
+    if value == "null" {
+        return Ok(None);
+    }
+    match value.parse::<i32>() {
+        Ok(v) if v > 0 => Ok(Some(v)),
+        _ => Err(S3Error::InvalidArgument),
+    }
+}
+
+/// ETag of the current version of `key`, `None` if there is none or it is a delete marker.
+pub async fn current_etag(
+    tx: &mut PgTransaction<'_>,
+    bucket_id: i32,
+    key: &str,
+) -> Result<Option<String>, Response> {
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            file_versions.is_delete_marker,
+            file_data.md5 AS "md5?", file_data.multipart_parts_count
+        FROM files
+            JOIN file_versions ON files.current_version = file_versions.id
+            LEFT JOIN file_data ON file_versions.file_data_id = file_data.id
+        WHERE files.bucket_id = $1 AND files.key = $2
+    "#,
+        bucket_id,
+        key
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    match result {
+        Ok(Some(v)) if !v.is_delete_marker => {
+            // objects without data are empty
+            let md5 = v.md5.unwrap_or_else(|| md5::Md5::digest([]).to_vec());
+            Ok(Some(format_etag(&md5, v.multipart_parts_count)))
+        }
+        Ok(_) => Ok(None),
+        Err(e) => {
+            tracing::error!("Failed to fetch current version: {:?}", e);
+            Err(S3Error::InternalError.into_response())
+        }
+    }
+}
+
+/// Creates a new version of `key` pointing at `file_data_id` (or a delete marker)
+/// and makes it the current one.
+///
+/// Unless versioning is enabled the new version is the "null" version, which replaces
+/// the previous null version of the object. Its data goes to `orphans` if nothing else
+/// refers to it.
+/// The `files` row is created if needed and stays locked until the transaction ends.
+///
+/// `condition` is checked again once the row is locked. Callers check it beforehand too, so
+/// a failure here means another write got in meanwhile: `ConditionalRequestConflict`.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_version(
+    tx: &mut PgTransaction<'_>,
+    bucket_id: i32,
+    key: &str,
+    file_data_id: Option<i32>,
+    is_delete_marker: bool,
+    versioning: Versioning,
+    metadata: &ObjectMetadata,
+    condition: Option<&WriteCondition>,
+    orphans: &mut Vec<BackendObject>,
+) -> Result<i32, Response> {
+    let result = sqlx::query!("SET CONSTRAINTS ALL DEFERRED")
+        .execute(&mut **tx)
+        .await;
+
+    match result {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to set constraints deferred: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    }
+
+    let file_id = sqlx::query!(
+        r#"
+        INSERT INTO
+            files(bucket_id, key, current_version, current_version_is_delete_marker)
+        VALUES ($1, $2, -1, FALSE)
+        ON CONFLICT DO NOTHING RETURNING id
+    "#,
+        bucket_id,
+        key
+    )
+    .fetch_optional(&mut **tx)
+    .await;
+
+    let file_id = match file_id {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to insert file: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let file_id = match file_id {
+        Some(v) => {
+            if let Some(Err(e)) = condition.map(|c| c.check(None)) {
+                tracing::info!("Write condition failed on a new object: {}", e);
+                return Err(S3Error::ConditionalRequestConflict.into_response());
+            }
+            v.id
+        }
+        None => {
+            let file_id = sqlx::query!(
+                "SELECT id FROM files WHERE bucket_id = $1 AND key = $2 LIMIT 1 FOR UPDATE",
+                bucket_id,
+                key
+            )
+            .fetch_one(&mut **tx)
+            .await;
+
+            let file_id = match file_id {
+                Ok(v) => v.id,
+                Err(e) => {
+                    tracing::error!("Failed to fetch file: {:?}", e);
+                    return Err(S3Error::InternalError.into_response());
+                }
+            };
+
+            if let Some(condition) = condition {
+                let etag = current_etag(tx, bucket_id, key).await?;
+                if let Err(e) = condition.check(etag.as_deref()) {
+                    tracing::info!("Write condition failed under lock: {}", e);
+                    return Err(S3Error::ConditionalRequestConflict.into_response());
+                }
+            }
+
+            file_id
+        }
+    };
+
+    let is_null_version = versioning != Versioning::Enabled;
+
+    let mut replaced_data_ids = Vec::new();
+    if is_null_version {
+        let result = sqlx::query!(
+            "DELETE FROM file_versions WHERE file_id = $1 AND is_null_version RETURNING file_data_id",
+            file_id
+        )
+        .fetch_all(&mut **tx)
+        .await;
+
+        match result {
+            Ok(v) => replaced_data_ids.extend(v.into_iter().filter_map(|v| v.file_data_id)),
+            Err(e) => {
+                tracing::error!("Failed to delete previous null version: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        }
+    }
+
+    let file_version_id = sqlx::query!(
+        r#"
+        INSERT INTO file_versions(
+            file_id, file_data_id, is_delete_marker, is_null_version,
+            content_type, content_encoding, content_disposition, content_language,
+            cache_control, expires, user_metadata
+        )
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING id
+    "#,
+        file_id,
+        file_data_id,
+        is_delete_marker,
+        is_null_version,
+        metadata.content_type,
+        metadata.content_encoding,
+        metadata.content_disposition,
+        metadata.content_language,
+        metadata.cache_control,
+        metadata.expires,
+        metadata.user_json() as _
+    )
+    .fetch_one(&mut **tx)
+    .await;
+
+    let file_version_id = match file_version_id {
+        Ok(v) => v.id,
+        Err(e) => {
+            tracing::error!("Failed to insert file version: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let update_file_result = sqlx::query!(
+        "UPDATE files SET current_version = $1, current_version_is_delete_marker = $2 WHERE id = $3",
+        file_version_id,
+        is_delete_marker,
+        file_id
+    )
+    .execute(&mut **tx)
+    .await;
+
+    match update_file_result {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to update file: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    // only now, as the new version may share the data of the replaced one
+    file_data::release_file_data(tx, &replaced_data_ids, orphans).await?;
+
+    Ok(file_version_id)
+}
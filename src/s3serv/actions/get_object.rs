@@ -1,205 +1,536 @@
+use std::{ops::Range, sync::Arc};
+
 use axum::{
     body::Body,
+    http::{HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
 };
-use sqlx::{postgres::types::PgRange, PgPool};
+use chrono::{DateTime, Utc};
+use md5::Digest;
+use sqlx::{postgres::types::PgRange, types::Json, PgPool};
+use tokio_stream::StreamExt;
 
-use crate::s3serv::error::S3Error;
+use super::{
+    bucket_versioning::Versioning,
+    conditions::{Evaluation, Preconditions},
+    file_versions,
+    object_metadata::{format_http_date, ObjectMetadata, ResponseHeaderOverrides, UserMetadata},
+};
+use crate::{
+    drivers::Drivers,
+    s3serv::{
+        checksum::{self, ChecksumAlgorithm},
+        error::S3Error,
+        etag::format_etag,
+    },
+};
 
-#[tracing::instrument]
-pub async fn head_object(pool: PgPool, bucket: String, key: String) -> Response {
-    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
-        .fetch_one(&pool)
-        .await;
+pub struct ObjectInfo {
+    pub data_id: Option<i32>,
+    pub version_id: Option<String>,
+    pub size: i64,
+    pub md5: Vec<u8>,
+    pub multipart_parts_count: Option<i32>,
+    pub last_modified: DateTime<Utc>,
+    pub metadata: ObjectMetadata,
+    /// The checksum the object was uploaded with.
+    pub checksum: Option<(ChecksumAlgorithm, Vec<u8>)>,
+}
 
-    let result = match result {
-        Ok(v) => v,
-        Err(e) => {
-            if let sqlx::Error::RowNotFound = e {
-                return S3Error::NoSuchBucket.into_response();
-            }
-            tracing::error!("Failed to fetch bucket: {:?}", e);
-            return S3Error::InternalError.into_response();
+impl ObjectInfo {
+    pub fn etag(&self) -> String {
+        format_etag(&self.md5, self.multipart_parts_count)
+    }
+}
+
+enum RequestedRange {
+    Full,
+    Partial(Range<i64>),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header against an object of `size` bytes.
+///
+/// Only a single `bytes` range is supported, like S3. Anything we can't understand
+/// (including multiple ranges) is ignored and the whole object is returned, as RFC 9110 allows.
+fn parse_range(header: Option<&HeaderValue>, size: i64) -> RequestedRange {
+    let Some(header) = header.and_then(|v| v.to_str().ok()) else {
+        return RequestedRange::Full;
+    };
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RequestedRange::Full;
+    };
+    if spec.contains(',') {
+        return RequestedRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RequestedRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // suffix range: last N bytes
+        let Ok(suffix) = end.parse::<i64>() else {
+            return RequestedRange::Full;
+        };
+        if suffix == 0 || size == 0 {
+            return RequestedRange::Unsatisfiable;
         }
+        return RequestedRange::Partial((size - suffix).max(0)..size);
+    }
+
+    let Ok(start) = start.parse::<i64>() else {
+        return RequestedRange::Full;
+    };
+    let end = if end.is_empty() {
+        size
+    } else {
+        match end.parse::<i64>() {
+            Ok(end) if end >= start => end.saturating_add(1).min(size),
+            _ => return RequestedRange::Full,
+        }
+    };
+
+    if start >= size {
+        return RequestedRange::Unsatisfiable;
+    }
+
+    RequestedRange::Partial(start..end)
+}
+
+struct VersionRow {
+    id: i32,
+    is_null_version: bool,
+    is_delete_marker: bool,
+    created_at: DateTime<Utc>,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    content_disposition: Option<String>,
+    content_language: Option<String>,
+    cache_control: Option<String>,
+    expires: Option<String>,
+    user_metadata: Option<Json<UserMetadata>>,
+    data_id: Option<i32>,
+    size: Option<i64>,
+    md5: Option<Vec<u8>>,
+    multipart_parts_count: Option<i32>,
+    crc32: Option<Vec<u8>>,
+    crc32c: Option<Vec<u8>>,
+    sha1: Option<Vec<u8>>,
+    sha256: Option<Vec<u8>>,
+    checksum_algorithm: Option<String>,
+}
+
+/// Looks up the current version of `key`, or the one given as `versionId`.
+pub async fn find_object(
+    pool: &PgPool,
+    bucket: &str,
+    key: &str,
+    version_id: Option<&str>,
+) -> Result<ObjectInfo, Response> {
+    let requested_version = match version_id.map(file_versions::parse_version_id) {
+        None => None,
+        Some(Ok(v)) => Some(v),
+        Some(Err(e)) => return Err(e.into_response()),
     };
 
     let result = sqlx::query!(
-        r#"
-        SELECT
-            file_versions.id as version_id,
-            file_data.id as data_id,
-            file_data.size, file_data.md5
-        FROM files
-            JOIN file_versions ON files.current_version = file_versions.id
-            JOIN file_data ON file_versions.file_data_id = file_data.id
-        WHERE
-            files.bucket_id = $1
-            AND files.key = $2
-            AND files.current_version_is_delete_marker = FALSE
-        LIMIT 1
-    "#,
-        result.id,
-        key
+        "SELECT id, versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
     )
-    .fetch_one(&pool)
+    .fetch_one(pool)
     .await;
 
-    let result = match result {
-        Ok(v) => v,
+    let (bucket_id, versioning) = match result {
+        Ok(v) => (v.id, v.versioning),
         Err(e) => {
             if let sqlx::Error::RowNotFound = e {
-                return S3Error::NoSuchKey.into_response();
+                return Err(S3Error::NoSuchBucket.into_response());
             }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return Err(S3Error::InternalError.into_response());
+        }
+    };
+
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return Err(e.into_response()),
+    };
+
+    let result = match requested_version {
+        None => {
+            sqlx::query_as!(
+                VersionRow,
+                r#"
+                SELECT
+                    file_versions.id, file_versions.is_null_version, file_versions.is_delete_marker,
+                    file_versions.created_at,
+                    file_versions.content_type, file_versions.content_encoding,
+                    file_versions.content_disposition, file_versions.content_language,
+                    file_versions.cache_control, file_versions.expires,
+                    file_versions.user_metadata AS "user_metadata: Json<UserMetadata>",
+                    file_data.id AS "data_id?",
+                    file_data.size AS "size?", file_data.md5 AS "md5?", file_data.multipart_parts_count,
+                    file_data.crc32, file_data.crc32c, file_data.sha1, file_data.sha256,
+                    file_data.checksum_algorithm
+                FROM files
+                    JOIN file_versions ON files.current_version = file_versions.id
+                    LEFT JOIN file_data ON file_versions.file_data_id = file_data.id
+                WHERE files.bucket_id = $1 AND files.key = $2
+                LIMIT 1
+            "#,
+                bucket_id,
+                key
+            )
+            .fetch_optional(pool)
+            .await
+        }
+        Some(version_id) => {
+            sqlx::query_as!(
+                VersionRow,
+                r#"
+                SELECT
+                    file_versions.id, file_versions.is_null_version, file_versions.is_delete_marker,
+                    file_versions.created_at,
+                    file_versions.content_type, file_versions.content_encoding,
+                    file_versions.content_disposition, file_versions.content_language,
+                    file_versions.cache_control, file_versions.expires,
+                    file_versions.user_metadata AS "user_metadata: Json<UserMetadata>",
+                    file_data.id AS "data_id?",
+                    file_data.size AS "size?", file_data.md5 AS "md5?", file_data.multipart_parts_count,
+                    file_data.crc32, file_data.crc32c, file_data.sha1, file_data.sha256,
+                    file_data.checksum_algorithm
+                FROM files
+                    JOIN file_versions ON file_versions.file_id = files.id
+                    LEFT JOIN file_data ON file_versions.file_data_id = file_data.id
+                WHERE
+                    files.bucket_id = $1
+                    AND files.key = $2
+                    AND (file_versions.id = $3 OR ($3 IS NULL AND file_versions.is_null_version))
+                LIMIT 1
+            "#,
+                bucket_id,
+                key,
+                version_id
+            )
+            .fetch_optional(pool)
+            .await
+        }
+    };
+
+    let row = match result {
+        Ok(Some(v)) => v,
+        Ok(None) if requested_version.is_some() => {
+            return Err(S3Error::NoSuchVersion.into_response())
+        }
+        Ok(None) => return Err(S3Error::NoSuchKey.into_response()),
+        Err(e) => {
             tracing::error!("Failed to fetch file: {:?}", e);
-            return S3Error::InternalError.into_response();
+            return Err(S3Error::InternalError.into_response());
         }
     };
 
-    println!("{:?}", result);
+    let version_id = (versioning != Versioning::Unversioned)
+        .then(|| file_versions::format_version_id(row.id, row.is_null_version));
 
-    if result.size == 0 {
-        return axum::http::StatusCode::OK.into_response();
+    if row.is_delete_marker {
+        // a delete marker has no data, but tell the client what it hit
+        let mut res = match requested_version {
+            Some(_) => S3Error::MethodNotAllowed.into_response(),
+            None => S3Error::NoSuchKey.into_response(),
+        };
+        res.headers_mut()
+            .insert("x-amz-delete-marker", HeaderValue::from_static("true"));
+        res.headers_mut().insert(
+            "Last-Modified",
+            HeaderValue::from_str(&format_http_date(&row.created_at)).unwrap(),
+        );
+        if let Some(version_id) = version_id {
+            res.headers_mut().insert(
+                "x-amz-version-id",
+                HeaderValue::from_str(&version_id).unwrap(),
+            );
+        }
+        return Err(res);
     }
 
-    let requested_range = 0..result.size; // TODO: HTTP Range header handling
+    let checksum = row
+        .checksum_algorithm
+        .as_deref()
+        .and_then(ChecksumAlgorithm::from_name)
+        .and_then(|algorithm| {
+            let value = match algorithm {
+                ChecksumAlgorithm::Crc32 => row.crc32,
+                ChecksumAlgorithm::Crc32c => row.crc32c,
+                ChecksumAlgorithm::Sha1 => row.sha1,
+                ChecksumAlgorithm::Sha256 => row.sha256,
+            };
+            value.map(|v| (algorithm, v))
+        });
 
-    (
-        axum::http::StatusCode::OK,
-        [
-            ("Content-Type", "video/mp4"),
-            ("Content-Length", result.size.to_string().as_str()),
-            ("ETag", format!("\"{}\"", hex::encode(result.md5)).as_str()),
-        ],
-    )
-        .into_response()
+    Ok(ObjectInfo {
+        data_id: row.data_id,
+        version_id,
+        size: row.size.unwrap_or(0),
+        // objects without data are empty
+        md5: row.md5.unwrap_or_else(|| md5::Md5::digest([]).to_vec()),
+        multipart_parts_count: row.multipart_parts_count,
+        last_modified: row.created_at,
+        metadata: ObjectMetadata {
+            content_type: row.content_type,
+            content_encoding: row.content_encoding,
+            content_disposition: row.content_disposition,
+            content_language: row.content_language,
+            cache_control: row.cache_control,
+            expires: row.expires,
+            user: row.user_metadata.map(|v| v.0).unwrap_or_default(),
+        },
+        checksum,
+    })
 }
 
-#[tracing::instrument]
-pub async fn get_object(pool: PgPool, bucket: String, key: String) -> Response {
-    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
-        .fetch_one(&pool)
-        .await;
+/// Evaluates `If-Match`, `If-None-Match`, `If-Modified-Since` and `If-Unmodified-Since`,
+/// giving the 304 or 412 response to send instead of the object.
+fn check_preconditions(object: &ObjectInfo, headers: &HeaderMap) -> Option<Response> {
+    let etag = object.etag();
+    match Preconditions::from_headers(headers, "").evaluate(&etag, &object.last_modified) {
+        Evaluation::Passed => None,
+        Evaluation::Failed => Some(S3Error::PreconditionFailed.into_response()),
+        Evaluation::NotModified => {
+            let mut headers = HeaderMap::new();
+            headers.insert("ETag", HeaderValue::from_str(&etag).unwrap());
+            headers.insert(
+                "Last-Modified",
+                HeaderValue::from_str(&format_http_date(&object.last_modified)).unwrap(),
+            );
+            if let Some(version_id) = &object.version_id {
+                headers.insert(
+                    "x-amz-version-id",
+                    HeaderValue::from_str(version_id).unwrap(),
+                );
+            }
+            Some((axum::http::StatusCode::NOT_MODIFIED, headers).into_response())
+        }
+    }
+}
 
-    let result = match result {
-        Ok(v) => v,
-        Err(e) => {
-            if let sqlx::Error::RowNotFound = e {
-                return S3Error::NoSuchBucket.into_response();
+fn range_not_satisfiable(size: i64) -> Response {
+    let mut res = S3Error::InvalidRange.into_response();
+    res.headers_mut().insert(
+        "Content-Range",
+        HeaderValue::from_str(&format!("bytes */{}", size)).unwrap(),
+    );
+    res
+}
+
+/// Whether the client sent `x-amz-checksum-mode: ENABLED`.
+fn checksum_mode_enabled(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-checksum-mode")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("ENABLED"))
+}
+
+fn object_headers(
+    object: &ObjectInfo,
+    range: &RequestedRange,
+    overrides: &ResponseHeaderOverrides,
+    checksum_mode: bool,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    object.metadata.write_headers(&mut headers);
+    overrides.write_headers(&mut headers);
+    headers.insert(
+        "Last-Modified",
+        HeaderValue::from_str(&format_http_date(&object.last_modified)).unwrap(),
+    );
+    headers.insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+    if let Some(version_id) = &object.version_id {
+        headers.insert(
+            "x-amz-version-id",
+            HeaderValue::from_str(version_id).unwrap(),
+        );
+    }
+    headers.insert("ETag", HeaderValue::from_str(&object.etag()).unwrap());
+    match range {
+        RequestedRange::Partial(range) => {
+            headers.insert("Content-Length", HeaderValue::from(range.end - range.start));
+            headers.insert(
+                "Content-Range",
+                HeaderValue::from_str(&format!(
+                    "bytes {}-{}/{}",
+                    range.start,
+                    range.end - 1,
+                    object.size
+                ))
+                .unwrap(),
+            );
+        }
+        _ => {
+            headers.insert("Content-Length", HeaderValue::from(object.size));
+            // the checksum covers the whole object, so it is not sent for ranges
+            if let Some((algorithm, value)) = object.checksum.as_ref().filter(|_| checksum_mode) {
+                headers.insert(
+                    algorithm.header_name(),
+                    HeaderValue::from_str(&checksum::encode_checksum(value)).unwrap(),
+                );
+                headers.insert(
+                    "x-amz-checksum-type",
+                    HeaderValue::from_static("FULL_OBJECT"),
+                );
             }
-            tracing::error!("Failed to fetch bucket: {:?}", e);
-            return S3Error::InternalError.into_response();
         }
+    }
+    headers
+}
+
+#[tracing::instrument(skip(pool, headers))]
+pub async fn head_object(
+    pool: PgPool,
+    bucket: String,
+    key: String,
+    version_id: Option<String>,
+    overrides: ResponseHeaderOverrides,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = overrides.validate() {
+        return e.into_response();
+    }
+
+    let object = match find_object(&pool, &bucket, &key, version_id.as_deref()).await {
+        Ok(v) => v,
+        Err(e) => return e,
     };
 
-    let result = sqlx::query!(
-        r#"
-        SELECT
-            file_versions.id as version_id,
-            file_data.id as data_id,
-            file_data.size, file_data.md5
-        FROM files
-            JOIN file_versions ON files.current_version = file_versions.id
-            JOIN file_data ON file_versions.file_data_id = file_data.id
-        WHERE
-            files.bucket_id = $1
-            AND files.key = $2
-            AND files.current_version_is_delete_marker = FALSE
-        LIMIT 1
-    "#,
-        result.id,
-        key
+    if let Some(res) = check_preconditions(&object, &headers) {
+        return res;
+    }
+
+    let range = parse_range(headers.get("Range"), object.size);
+    let status = match range {
+        RequestedRange::Full => axum::http::StatusCode::OK,
+        RequestedRange::Partial(_) => axum::http::StatusCode::PARTIAL_CONTENT,
+        RequestedRange::Unsatisfiable => return range_not_satisfiable(object.size),
+    };
+
+    (
+        status,
+        object_headers(&object, &range, &overrides, checksum_mode_enabled(&headers)),
     )
-    .fetch_one(&pool)
-    .await;
+        .into_response()
+}
+
+#[tracing::instrument(skip(pool, drivers, headers))]
+pub async fn get_object(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    version_id: Option<String>,
+    overrides: ResponseHeaderOverrides,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = overrides.validate() {
+        return e.into_response();
+    }
 
-    let result = match result {
+    let object = match find_object(&pool, &bucket, &key, version_id.as_deref()).await {
         Ok(v) => v,
-        Err(e) => {
-            if let sqlx::Error::RowNotFound = e {
-                return S3Error::NoSuchKey.into_response();
-            }
-            tracing::error!("Failed to fetch file: {:?}", e);
-            return S3Error::InternalError.into_response();
-        }
+        Err(e) => return e,
     };
 
-    println!("{:?}", result);
-
-    if result.size == 0 {
-        return axum::http::StatusCode::OK.into_response();
+    if let Some(res) = check_preconditions(&object, &headers) {
+        return res;
     }
 
-    let requested_range = 0..result.size; // TODO: HTTP Range header handling
+    let range = parse_range(headers.get("Range"), object.size);
+    let (status, requested_range) = match &range {
+        RequestedRange::Full => (axum::http::StatusCode::OK, 0..object.size),
+        RequestedRange::Partial(range) => (axum::http::StatusCode::PARTIAL_CONTENT, range.clone()),
+        RequestedRange::Unsatisfiable => return range_not_satisfiable(object.size),
+    };
 
+    let Some(data_id) = object.data_id.filter(|_| object.size > 0) else {
+        return (
+            status,
+            object_headers(&object, &range, &overrides, checksum_mode_enabled(&headers)),
+        )
+            .into_response();
+    };
+
+    // a part may be entered in the middle, so start reading from the beginning of
+    // the backend chunk that contains the first requested byte of the part
     let parts = sqlx::query!(
-        "SELECT backend_key, range FROM file_data_parts WHERE file_data_id = $1 AND range && $2",
-        result.data_id,
-        PgRange::from(requested_range.clone())
+        r#"
+        SELECT
+            driver, backend_key, lower(range) AS "start!", upper(range) AS "end!", backend_offset,
+            (
+                SELECT lower(file_data_part_chunk_info.range)
+                FROM file_data_part_chunk_info
+                WHERE
+                    file_data_part_chunk_info.part_id = file_data_parts.id
+                    AND lower(file_data_part_chunk_info.range)
+                        <= GREATEST($3 - lower(file_data_parts.range), 0) + backend_offset
+                ORDER BY lower(file_data_part_chunk_info.range) DESC
+                LIMIT 1
+            ) AS chunk_start
+        FROM file_data_parts
+        WHERE file_data_id = $1 AND range && $2
+        ORDER BY lower(range)
+    "#,
+        data_id,
+        PgRange::from(requested_range.clone()),
+        requested_range.start
     )
-    .fetch_one(&pool)
+    .fetch_all(&pool)
     .await;
 
     let parts = match parts {
         Ok(v) => v,
         Err(e) => {
-            if let sqlx::Error::RowNotFound = e {
-                tracing::error!("Requested range not found: {:?}", requested_range);
-                return S3Error::InternalError.into_response();
-            }
             tracing::error!("Failed to fetch file parts: {:?}", e);
             return S3Error::InternalError.into_response();
         }
     };
 
-    let stream = async_stream::stream! {
-        let mut offset = requested_range.start;
-        let client = reqwest::Client::new();
-        while offset < requested_range.end {
-            let res = client.get(format!("http://localhost:4000/v1/files/{}/chunks/{}", parts.backend_key, offset))
-                .send()
-                .await;
-
-            let res = match res {
-                Ok(v) => v,
-                Err(e) => {
-                    tracing::error!("Failed to fetch file part: {:?}", e);
-                    yield Err(e);
-                    return;
-                }
-            };
-
-            let res = match res.error_for_status() {
-                Ok(v) => v,
-                Err(e) => {
-                    tracing::error!("Failed to fetch file part: {:?}", e);
-                    yield Err(e);
-                    return;
-                }
-            };
+    if parts.is_empty() {
+        tracing::error!("Requested range not found: {:?}", requested_range);
+        return S3Error::InternalError.into_response();
+    }
 
-            let res = res.bytes().await;
+    let mut reads = Vec::with_capacity(parts.len());
+    for part in parts {
+        let Some(driver) = drivers.get(&part.driver) else {
+            tracing::error!("Unknown storage driver: {}", part.driver);
+            return S3Error::InternalError.into_response();
+        };
+        // offsets inside the backend object
+        let want = (requested_range.start.max(part.start) - part.start + part.backend_offset)
+            ..(requested_range.end.min(part.end) - part.start + part.backend_offset);
+        reads.push((
+            driver.clone(),
+            part.backend_key,
+            want,
+            part.chunk_start.unwrap_or(0),
+        ));
+    }
 
-            let res = match res {
-                Ok(v) => v,
-                Err(e) => {
-                    tracing::error!("Failed to read file part: {:?}", e);
-                    yield Err(e);
+    let stream = async_stream::stream! {
+        for (driver, backend_key, want, chunk_start) in reads {
+            let mut part = driver.read_range(&backend_key, want, chunk_start);
+            while let Some(v) = part.next().await {
+                let failed = v.is_err();
+                yield v;
+                if failed {
                     return;
                 }
-            };
-
-            offset += res.len() as i64;
-            yield Ok(res);
+            }
         }
     };
 
     (
-        axum::http::StatusCode::OK,
-        [
-            ("Content-Type", "video/mp4"), // TODO: correctly handles metadata on both of get_ and put_object
-            ("Content-Length", result.size.to_string().as_str()),
-            ("ETag", format!("\"{}\"", hex::encode(result.md5)).as_str()),
-        ],
+        status,
+        object_headers(&object, &range, &overrides, checksum_mode_enabled(&headers)),
         Body::from_stream(stream),
     )
         .into_response()
@@ -0,0 +1,135 @@
+use axum::response::{IntoResponse, Response};
+use chrono::SecondsFormat;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::s3serv::error::S3Error;
+
+#[derive(serde::Serialize)]
+struct ListMultipartUploadsResult {
+    #[serde(rename = "Bucket")]
+    bucket: String,
+    #[serde(rename = "KeyMarker")]
+    key_marker: String,
+    #[serde(rename = "UploadIdMarker")]
+    upload_id_marker: String,
+    #[serde(rename = "NextKeyMarker")]
+    next_key_marker: String,
+    #[serde(rename = "NextUploadIdMarker")]
+    next_upload_id_marker: String,
+    #[serde(rename = "Prefix")]
+    prefix: String,
+    #[serde(rename = "MaxUploads")]
+    max_uploads: i64,
+    #[serde(rename = "IsTruncated")]
+    is_truncated: bool,
+    #[serde(rename = "Upload")]
+    uploads: Vec<Upload>,
+}
+
+#[derive(serde::Serialize)]
+struct Upload {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "UploadId")]
+    upload_id: String,
+    #[serde(rename = "Initiated")]
+    initiated: String,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn list_multipart_uploads(
+    pool: PgPool,
+    bucket: String,
+    prefix: String,
+    key_marker: String,
+    upload_id_marker: String,
+    max_uploads: Option<String>,
+) -> Response {
+    let max_uploads = match max_uploads.as_deref().map(str::parse::<i64>) {
+        None => 1000,
+        Some(Ok(v)) if v >= 0 => v.min(1000),
+        Some(_) => return S3Error::InvalidArgument.into_response(),
+    };
+
+    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
+        .fetch_one(&pool)
+        .await;
+
+    let bucket_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    // uploads are ordered by key and then by initiation, so the upload id marker
+    // only makes sense together with the key marker
+    let result = sqlx::query!(
+        r#"
+        SELECT key, upload_id, created_at
+        FROM multipart_uploads
+        WHERE
+            bucket_id = $1
+            AND starts_with(key, $2)
+            AND (
+                key > $3
+                OR (key = $3 AND $4 <> '' AND id > COALESCE((SELECT id FROM multipart_uploads WHERE upload_id = $4), 0))
+            )
+        ORDER BY key, id
+        LIMIT $5
+    "#,
+        bucket_id,
+        prefix,
+        key_marker,
+        upload_id_marker,
+        max_uploads + 1
+    )
+    .fetch_all(&pool)
+    .await;
+
+    let mut result = match result {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to fetch multipart uploads: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let is_truncated = result.len() as i64 > max_uploads;
+    result.truncate(max_uploads as usize);
+
+    let (next_key_marker, next_upload_id_marker) = match result.last() {
+        Some(last) if is_truncated => (last.key.clone(), last.upload_id.clone()),
+        _ => (String::new(), String::new()),
+    };
+
+    let result = ListMultipartUploadsResult {
+        bucket,
+        key_marker,
+        upload_id_marker,
+        next_key_marker,
+        next_upload_id_marker,
+        prefix,
+        max_uploads,
+        is_truncated,
+        uploads: result
+            .into_iter()
+            .map(|upload| Upload {
+                key: upload.key,
+                upload_id: upload.upload_id,
+                initiated: upload.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            })
+            .collect(),
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
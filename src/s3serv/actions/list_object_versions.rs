@@ -0,0 +1,294 @@
+use axum::response::{IntoResponse, Response};
+use chrono::SecondsFormat;
+use md5::Digest;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use super::{file_versions::format_version_id, listing};
+use crate::s3serv::{error::S3Error, etag::format_etag};
+
+#[derive(serde::Serialize)]
+struct ListVersionsResult {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Prefix")]
+    prefix: String,
+    #[serde(rename = "KeyMarker")]
+    key_marker: String,
+    #[serde(rename = "VersionIdMarker")]
+    version_id_marker: String,
+    #[serde(rename = "NextKeyMarker", skip_serializing_if = "Option::is_none")]
+    next_key_marker: Option<String>,
+    #[serde(
+        rename = "NextVersionIdMarker",
+        skip_serializing_if = "Option::is_none"
+    )]
+    next_version_id_marker: Option<String>,
+    #[serde(rename = "MaxKeys")]
+    max_keys: i64,
+    #[serde(rename = "Delimiter", skip_serializing_if = "String::is_empty")]
+    delimiter: String,
+    #[serde(rename = "IsTruncated")]
+    is_truncated: bool,
+    #[serde(rename = "$value")]
+    entries: Vec<Entry>,
+    #[serde(rename = "CommonPrefixes")]
+    common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(serde::Serialize)]
+enum Entry {
+    Version(Version),
+    DeleteMarker(DeleteMarker),
+}
+
+#[derive(serde::Serialize)]
+struct Version {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "VersionId")]
+    version_id: String,
+    #[serde(rename = "IsLatest")]
+    is_latest: bool,
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+    #[serde(rename = "Size")]
+    size: i64,
+    #[serde(rename = "StorageClass")]
+    storage_class: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct DeleteMarker {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "VersionId")]
+    version_id: String,
+    #[serde(rename = "IsLatest")]
+    is_latest: bool,
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+}
+
+#[derive(serde::Serialize)]
+struct CommonPrefix {
+    #[serde(rename = "Prefix")]
+    prefix: String,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn list_object_versions(
+    pool: PgPool,
+    bucket: String,
+    prefix: String,
+    delimiter: String,
+    key_marker: String,
+    version_id_marker: String,
+    max_keys: Option<String>,
+) -> Response {
+    let Some(max_keys) = listing::parse_max_keys(max_keys.as_deref()) else {
+        return S3Error::InvalidArgument.into_response();
+    };
+
+    if key_marker.is_empty() && !version_id_marker.is_empty() {
+        return S3Error::InvalidArgument.into_response();
+    }
+
+    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
+        .fetch_one(&pool)
+        .await;
+
+    let bucket_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchBucket.into_response();
+            }
+            tracing::error!("Failed to fetch bucket: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    // entries are ordered by key, and then by version from newest to oldest.
+    // the listing continues after (pos_key, pos_version_id) in that order.
+    let mut pos_key = key_marker.clone();
+    let mut pos_version_id = i32::MIN;
+    if version_id_marker == "null" {
+        let result = sqlx::query!(
+            r#"
+            SELECT file_versions.id
+            FROM files
+                JOIN file_versions ON file_versions.file_id = files.id
+            WHERE files.bucket_id = $1 AND files.key = $2 AND file_versions.is_null_version
+        "#,
+            bucket_id,
+            key_marker
+        )
+        .fetch_optional(&pool)
+        .await;
+
+        match result {
+            Ok(Some(v)) => pos_version_id = v.id,
+            Ok(None) => return S3Error::InvalidArgument.into_response(),
+            Err(e) => {
+                tracing::error!("Failed to fetch null version: {:?}", e);
+                return S3Error::InternalError.into_response();
+            }
+        }
+    } else if !version_id_marker.is_empty() {
+        match version_id_marker.parse::<i32>() {
+            Ok(v) => pos_version_id = v,
+            Err(_) => return S3Error::InvalidArgument.into_response(),
+        }
+    }
+
+    // a marker inside a common prefix means the whole prefix was already returned
+    let mut exhausted = false;
+    if let Some(common_prefix) = listing::common_prefix(&key_marker, &prefix, &delimiter) {
+        match listing::prefix_successor(common_prefix) {
+            Some(next) => {
+                pos_key = next;
+                pos_version_id = i32::MAX;
+            }
+            None => exhausted = true,
+        }
+    }
+
+    let pattern = listing::like_prefix_pattern(&prefix);
+    let mut entries = Vec::new();
+    let mut common_prefixes: Vec<CommonPrefix> = Vec::new();
+    let mut next_marker: Option<(String, Option<String>)> = None;
+    let mut is_truncated = false;
+
+    // like S3, max-keys=0 lists nothing and is not truncated, there is no token to go on from
+    if max_keys == 0 {
+        exhausted = true;
+    }
+
+    'fetch: while !exhausted {
+        let limit = max_keys + 1 - (entries.len() + common_prefixes.len()) as i64;
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                files.key,
+                file_versions.id,
+                file_versions.is_null_version,
+                file_versions.is_delete_marker,
+                file_versions.created_at,
+                files.current_version = file_versions.id AS "is_latest!",
+                file_data.size AS "size?",
+                file_data.md5 AS "md5?",
+                file_data.multipart_parts_count
+            FROM files
+                JOIN file_versions ON file_versions.file_id = files.id
+                LEFT JOIN file_data ON file_data.id = file_versions.file_data_id
+            WHERE
+                files.bucket_id = $1
+                AND files.key LIKE $2
+                AND (files.key > $3 OR (files.key = $3 AND file_versions.id < $4))
+            ORDER BY files.key, file_versions.id DESC
+            LIMIT $5
+        "#,
+            bucket_id,
+            pattern,
+            pos_key,
+            pos_version_id,
+            limit
+        )
+        .fetch_all(&pool)
+        .await;
+
+        let rows = match rows {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to fetch object versions: {:?}", e);
+                return S3Error::InternalError.into_response();
+            }
+        };
+
+        let fetched = rows.len() as i64;
+
+        for row in rows {
+            if (entries.len() + common_prefixes.len()) as i64 >= max_keys {
+                is_truncated = true;
+                break 'fetch;
+            }
+
+            if let Some(common_prefix) = listing::common_prefix(&row.key, &prefix, &delimiter) {
+                common_prefixes.push(CommonPrefix {
+                    prefix: common_prefix.to_string(),
+                });
+                next_marker = Some((common_prefix.to_string(), None));
+                // skip everything else under this common prefix
+                match listing::prefix_successor(common_prefix) {
+                    Some(next) => {
+                        pos_key = next;
+                        pos_version_id = i32::MAX;
+                    }
+                    None => exhausted = true,
+                }
+                continue 'fetch;
+            }
+
+            let version_id = format_version_id(row.id, row.is_null_version);
+            let last_modified = row.created_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+            next_marker = Some((row.key.clone(), Some(version_id.clone())));
+            pos_key = row.key.clone();
+            pos_version_id = row.id;
+
+            if row.is_delete_marker {
+                entries.push(Entry::DeleteMarker(DeleteMarker {
+                    key: row.key,
+                    version_id,
+                    is_latest: row.is_latest,
+                    last_modified,
+                }));
+            } else {
+                let etag = match &row.md5 {
+                    Some(md5) => format_etag(md5, row.multipart_parts_count),
+                    None => format_etag(&md5::Md5::digest([]), None),
+                };
+                entries.push(Entry::Version(Version {
+                    key: row.key,
+                    version_id,
+                    is_latest: row.is_latest,
+                    last_modified,
+                    etag,
+                    size: row.size.unwrap_or(0),
+                    storage_class: "STANDARD",
+                }));
+            }
+        }
+
+        if fetched < limit {
+            break;
+        }
+    }
+
+    let (next_key_marker, next_version_id_marker) = match next_marker {
+        Some((key, version_id)) if is_truncated => (Some(key), version_id),
+        _ => (None, None),
+    };
+
+    let result = ListVersionsResult {
+        name: bucket,
+        prefix,
+        key_marker,
+        version_id_marker,
+        next_key_marker,
+        next_version_id_marker,
+        max_keys,
+        delimiter,
+        is_truncated,
+        entries,
+        common_prefixes,
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
@@ -1,31 +1,128 @@
 use axum::response::{IntoResponse, Response};
+use chrono::SecondsFormat;
+use md5::Digest;
 use serde::Serialize;
 use sqlx::PgPool;
 
-use crate::s3serv::error::S3Error;
+use super::listing;
+use crate::s3serv::{error::S3Error, etag::format_etag};
+
+/// Query parameters of ListObjects (V1) and ListObjectsV2.
+pub struct ListObjectsOptions {
+    pub list_type: Option<String>,
+    pub prefix: String,
+    pub delimiter: String,
+    pub max_keys: Option<String>,
+    pub encoding_type: Option<String>,
+    // V1
+    pub marker: Option<String>,
+    // V2
+    pub continuation_token: Option<String>,
+    pub start_after: Option<String>,
+}
 
 #[derive(serde::Serialize)]
 struct ListBucketResult {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Prefix")]
+    prefix: String,
+    #[serde(rename = "Marker", skip_serializing_if = "Option::is_none")]
+    marker: Option<String>,
+    #[serde(rename = "NextMarker", skip_serializing_if = "Option::is_none")]
+    next_marker: Option<String>,
+    #[serde(rename = "StartAfter", skip_serializing_if = "Option::is_none")]
+    start_after: Option<String>,
+    #[serde(rename = "ContinuationToken", skip_serializing_if = "Option::is_none")]
+    continuation_token: Option<String>,
+    #[serde(
+        rename = "NextContinuationToken",
+        skip_serializing_if = "Option::is_none"
+    )]
+    next_continuation_token: Option<String>,
+    #[serde(rename = "KeyCount", skip_serializing_if = "Option::is_none")]
+    key_count: Option<usize>,
+    #[serde(rename = "MaxKeys")]
+    max_keys: i64,
+    #[serde(rename = "Delimiter", skip_serializing_if = "String::is_empty")]
+    delimiter: String,
+    #[serde(rename = "EncodingType", skip_serializing_if = "Option::is_none")]
+    encoding_type: Option<&'static str>,
+    #[serde(rename = "IsTruncated")]
+    is_truncated: bool,
     #[serde(rename = "Contents")]
     contents: Vec<Content>,
+    #[serde(rename = "CommonPrefixes")]
+    common_prefixes: Vec<CommonPrefix>,
 }
 
 #[derive(serde::Serialize)]
 struct Content {
     #[serde(rename = "Key")]
     key: String,
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
     #[serde(rename = "Size")]
     size: u64,
+    #[serde(rename = "StorageClass")]
+    storage_class: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct CommonPrefix {
+    #[serde(rename = "Prefix")]
+    prefix: String,
 }
 
-#[tracing::instrument(skip(pool))]
-pub async fn list_objects(pool: PgPool, bucket: String, prefix: String) -> Response {
+#[tracing::instrument(skip(pool, options))]
+pub async fn list_objects(pool: PgPool, bucket: String, options: ListObjectsOptions) -> Response {
+    let is_v2 = match options.list_type.as_deref() {
+        None => false,
+        Some("2") => true,
+        Some(_) => return S3Error::InvalidArgument.into_response(),
+    };
+
+    let url_encoded = match options.encoding_type.as_deref() {
+        None => false,
+        Some("url") => true,
+        Some(_) => return S3Error::InvalidArgument.into_response(),
+    };
+    let encode = |value: String| {
+        if url_encoded {
+            listing::url_encode(&value)
+        } else {
+            value
+        }
+    };
+
+    let Some(max_keys) = listing::parse_max_keys(options.max_keys.as_deref()) else {
+        return S3Error::InvalidArgument.into_response();
+    };
+
+    // the continuation token is the hex encoded last key (or common prefix) we returned
+    let start_key = if is_v2 {
+        match &options.continuation_token {
+            Some(token) => match hex::decode(token)
+                .ok()
+                .and_then(|v| String::from_utf8(v).ok())
+            {
+                Some(v) => v,
+                None => return S3Error::InvalidArgument.into_response(),
+            },
+            None => options.start_after.clone().unwrap_or_default(),
+        }
+    } else {
+        options.marker.clone().unwrap_or_default()
+    };
+
     let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
         .fetch_one(&pool)
         .await;
 
-    let result = match result {
-        Ok(v) => v,
+    let bucket_id = match result {
+        Ok(v) => v.id,
         Err(e) => {
             if let sqlx::Error::RowNotFound = e {
                 return S3Error::NoSuchBucket.into_response();
@@ -35,38 +132,158 @@ pub async fn list_objects(pool: PgPool, bucket: String, prefix: String) -> Respo
         }
     };
 
-    let result = sqlx::query!(
-        r#"
-        SELECT files.key, COALESCE(file_data.size, 0) AS "size!"
-        FROM files
-            LEFT JOIN file_versions ON file_versions.id = files.current_version
-            LEFT JOIN file_data ON file_data.id = file_versions.file_data_id
-        WHERE
-            files.bucket_id = $1
-            AND files.key LIKE $2
-    "#,
-        result.id,
-        format!("{}%", prefix)
-    )
-    .fetch_all(&pool)
-    .await;
-
-    let result = match result {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("Failed to fetch objects: {:?}", e);
-            return S3Error::InternalError.into_response();
+    let prefix = options.prefix;
+    let delimiter = options.delimiter;
+
+    // the listing continues after pos_key (or from it, when inclusive)
+    let mut pos_key = start_key.clone();
+    let mut inclusive = false;
+
+    // a marker inside a common prefix means the whole prefix was already returned
+    let mut exhausted = false;
+    if let Some(common_prefix) = listing::common_prefix(&start_key, &prefix, &delimiter) {
+        match listing::prefix_successor(common_prefix) {
+            Some(next) => {
+                pos_key = next;
+                inclusive = true;
+            }
+            None => exhausted = true,
         }
-    };
+    }
+
+    let pattern = listing::like_prefix_pattern(&prefix);
+    let mut contents = Vec::new();
+    let mut common_prefixes: Vec<CommonPrefix> = Vec::new();
+    let mut last_key: Option<String> = None;
+    let mut is_truncated = false;
+
+    // like S3, max-keys=0 lists nothing and is not truncated, there is no token to go on from
+    if max_keys == 0 {
+        exhausted = true;
+    }
+
+    'fetch: while !exhausted {
+        let limit = max_keys + 1 - (contents.len() + common_prefixes.len()) as i64;
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                files.key,
+                file_versions.created_at,
+                file_data.size AS "size?",
+                file_data.md5 AS "md5?",
+                file_data.multipart_parts_count
+            FROM files
+                JOIN file_versions ON file_versions.id = files.current_version
+                LEFT JOIN file_data ON file_data.id = file_versions.file_data_id
+            WHERE
+                files.bucket_id = $1
+                AND files.key LIKE $2
+                AND (files.key > $3 OR (files.key = $3 AND $4))
+                AND files.current_version_is_delete_marker = FALSE
+            ORDER BY files.key
+            LIMIT $5
+        "#,
+            bucket_id,
+            pattern,
+            pos_key,
+            inclusive,
+            limit
+        )
+        .fetch_all(&pool)
+        .await;
+
+        let rows = match rows {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to fetch objects: {:?}", e);
+                return S3Error::InternalError.into_response();
+            }
+        };
+
+        let fetched = rows.len() as i64;
+
+        for row in rows {
+            if (contents.len() + common_prefixes.len()) as i64 >= max_keys {
+                is_truncated = true;
+                break 'fetch;
+            }
 
-    let result = ListBucketResult {
-        contents: result
-            .into_iter()
-            .map(|object| Content {
-                key: object.key,
-                size: object.size as u64,
-            })
-            .collect(),
+            if let Some(common_prefix) = listing::common_prefix(&row.key, &prefix, &delimiter) {
+                common_prefixes.push(CommonPrefix {
+                    prefix: encode(common_prefix.to_string()),
+                });
+                last_key = Some(common_prefix.to_string());
+                // skip everything else under this common prefix
+                match listing::prefix_successor(common_prefix) {
+                    Some(next) => {
+                        pos_key = next;
+                        inclusive = true;
+                    }
+                    None => exhausted = true,
+                }
+                continue 'fetch;
+            }
+
+            last_key = Some(row.key.clone());
+            pos_key = row.key.clone();
+            inclusive = false;
+
+            let etag = match &row.md5 {
+                Some(md5) => format_etag(md5, row.multipart_parts_count),
+                None => format_etag(&md5::Md5::digest([]), None),
+            };
+            contents.push(Content {
+                key: encode(row.key),
+                last_modified: row.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                etag,
+                size: row.size.unwrap_or(0) as u64,
+                storage_class: "STANDARD",
+            });
+        }
+
+        if fetched < limit {
+            break;
+        }
+    }
+
+    let next_key = last_key.filter(|_| is_truncated);
+
+    let result = if is_v2 {
+        ListBucketResult {
+            name: bucket,
+            prefix: encode(prefix),
+            marker: None,
+            next_marker: None,
+            start_after: options.start_after.map(encode),
+            continuation_token: options.continuation_token,
+            next_continuation_token: next_key.map(hex::encode),
+            key_count: Some(contents.len() + common_prefixes.len()),
+            max_keys,
+            delimiter: encode(delimiter),
+            encoding_type: url_encoded.then_some("url"),
+            is_truncated,
+            contents,
+            common_prefixes,
+        }
+    } else {
+        ListBucketResult {
+            name: bucket,
+            prefix: encode(prefix),
+            marker: Some(encode(start_key)),
+            // like S3, NextMarker is only returned when a delimiter is given;
+            // otherwise clients continue from the last key
+            next_marker: next_key.filter(|_| !delimiter.is_empty()).map(encode),
+            start_after: None,
+            continuation_token: None,
+            next_continuation_token: None,
+            key_count: None,
+            max_keys,
+            delimiter: encode(delimiter),
+            encoding_type: url_encoded.then_some("url"),
+            is_truncated,
+            contents,
+            common_prefixes,
+        }
     };
 
     let mut buffer = String::new();
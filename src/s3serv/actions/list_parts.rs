@@ -0,0 +1,139 @@
+use axum::response::{IntoResponse, Response};
+use chrono::SecondsFormat;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::s3serv::{error::S3Error, etag::format_etag};
+
+#[derive(serde::Serialize)]
+struct ListPartsResult {
+    #[serde(rename = "Bucket")]
+    bucket: String,
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "UploadId")]
+    upload_id: String,
+    #[serde(rename = "PartNumberMarker")]
+    part_number_marker: i32,
+    #[serde(rename = "NextPartNumberMarker")]
+    next_part_number_marker: i32,
+    #[serde(rename = "MaxParts")]
+    max_parts: i64,
+    #[serde(rename = "IsTruncated")]
+    is_truncated: bool,
+    #[serde(rename = "Part")]
+    parts: Vec<Part>,
+}
+
+#[derive(serde::Serialize)]
+struct Part {
+    #[serde(rename = "PartNumber")]
+    part_number: i32,
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+    #[serde(rename = "Size")]
+    size: i64,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn list_parts(
+    pool: PgPool,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    max_parts: Option<String>,
+    part_number_marker: Option<String>,
+) -> Response {
+    let max_parts = match max_parts.as_deref().map(str::parse::<i64>) {
+        None => 1000,
+        Some(Ok(v)) if v >= 0 => v.min(1000),
+        Some(_) => return S3Error::InvalidArgument.into_response(),
+    };
+    let part_number_marker = match part_number_marker.as_deref().map(str::parse::<i32>) {
+        None => 0,
+        Some(Ok(v)) if v >= 0 => v,
+        Some(_) => return S3Error::InvalidArgument.into_response(),
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT multipart_uploads.id
+        FROM multipart_uploads
+            JOIN buckets ON buckets.id = multipart_uploads.bucket_id
+        WHERE
+            buckets.name = $1
+            AND multipart_uploads.key = $2
+            AND multipart_uploads.upload_id = $3
+    "#,
+        bucket,
+        key,
+        upload_id
+    )
+    .fetch_one(&pool)
+    .await;
+
+    let multipart_upload_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchUpload.into_response();
+            }
+            tracing::error!("Failed to fetch multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    // fetch one more than requested to know whether the listing is truncated
+    let result = sqlx::query!(
+        r#"
+        SELECT part_number, size, md5, created_at
+        FROM multipart_upload_parts
+        WHERE multipart_upload_id = $1 AND part_number > $2
+        ORDER BY part_number
+        LIMIT $3
+    "#,
+        multipart_upload_id,
+        part_number_marker,
+        max_parts + 1
+    )
+    .fetch_all(&pool)
+    .await;
+
+    let mut result = match result {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to fetch multipart upload parts: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let is_truncated = result.len() as i64 > max_parts;
+    result.truncate(max_parts as usize);
+
+    let result = ListPartsResult {
+        bucket,
+        key,
+        upload_id,
+        part_number_marker,
+        next_part_number_marker: result.last().map(|p| p.part_number).unwrap_or(0),
+        max_parts,
+        is_truncated,
+        parts: result
+            .into_iter()
+            .map(|part| Part {
+                part_number: part.part_number,
+                last_modified: part.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                etag: format_etag(&part.md5, None),
+                size: part.size,
+            })
+            .collect(),
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    ([("Content-Type", "application/xml")], buffer).into_response()
+}
@@ -0,0 +1,63 @@
+/// Default (and maximum) number of entries returned by a listing.
+pub const MAX_KEYS: i64 = 1000;
+
+/// Parses `max-keys` style query values, capping them to [`MAX_KEYS`].
+pub fn parse_max_keys(value: Option<&str>) -> Option<i64> {
+    match value.map(str::parse::<i64>) {
+        None => Some(MAX_KEYS),
+        Some(Ok(v)) if v >= 0 => Some(v.min(MAX_KEYS)),
+        Some(_) => None,
+    }
+}
+
+/// Builds a `LIKE` pattern matching every key starting with `prefix`.
+pub fn like_prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Returns the common prefix `key` is rolled up into, if any.
+pub fn common_prefix<'a>(key: &'a str, prefix: &str, delimiter: &str) -> Option<&'a str> {
+    if delimiter.is_empty() {
+        return None;
+    }
+    let rest = key.strip_prefix(prefix)?;
+    let index = rest.find(delimiter)?;
+    Some(&key[..prefix.len() + index + delimiter.len()])
+}
+
+/// Returns the smallest string that sorts after every string starting with `prefix`,
+/// in UTF-8 binary (= code point) order.
+pub fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+const KEY_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Encodes a key for `encoding-type=url` responses.
+pub fn url_encode(value: &str) -> String {
+    percent_encoding::utf8_percent_encode(value, KEY_ENCODE_SET).to_string()
+}
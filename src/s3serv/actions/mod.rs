@@ -1,13 +1,43 @@
+mod abort_multipart_upload;
+mod bucket_versioning;
+mod complete_multipart_upload;
+mod conditions;
+mod copy_object;
 mod create_bucket;
+mod create_multipart_upload;
 mod delete_bucket;
+mod delete_object;
+mod delete_objects;
+mod file_data;
+mod file_versions;
 mod get_object;
 mod list_buckets;
+mod list_multipart_uploads;
+mod list_object_versions;
 mod list_objects;
+mod list_parts;
+mod listing;
+mod object_metadata;
 mod put_object;
+mod upload_part;
+mod upload_part_copy;
 
+pub use abort_multipart_upload::abort_multipart_upload;
+pub use bucket_versioning::{get_bucket_versioning, put_bucket_versioning};
+pub use complete_multipart_upload::complete_multipart_upload;
+pub use copy_object::copy_object;
 pub use create_bucket::create_bucket;
+pub use create_multipart_upload::create_multipart_upload;
 pub use delete_bucket::delete_bucket;
+pub use delete_object::delete_object;
+pub use delete_objects::delete_objects;
 pub use get_object::{get_object, head_object};
 pub use list_buckets::list_buckets;
-pub use list_objects::list_objects;
+pub use list_multipart_uploads::list_multipart_uploads;
+pub use list_object_versions::list_object_versions;
+pub use list_objects::{list_objects, ListObjectsOptions};
+pub use list_parts::list_parts;
+pub use object_metadata::ResponseHeaderOverrides;
 pub use put_object::put_object;
+pub use upload_part::upload_part;
+pub use upload_part_copy::upload_part_copy;
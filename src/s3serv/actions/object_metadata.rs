@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+
+use crate::s3serv::error::S3Error;
+
+/// Content-Type of objects stored without one.
+const DEFAULT_CONTENT_TYPE: &str = "binary/octet-stream";
+
+const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
+/// S3 limits the user metadata (names plus values) to 2 KB.
+const MAX_USER_METADATA_SIZE: usize = 2 * 1024;
+
+/// User metadata as stored in the `user_metadata` JSONB columns,
+/// keyed by the lowercased header name without the `x-amz-meta-` prefix.
+pub type UserMetadata = BTreeMap<String, String>;
+
+/// System and user metadata stored with an object version.
+#[derive(Default, Debug)]
+pub struct ObjectMetadata {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_language: Option<String>,
+    pub cache_control: Option<String>,
+    pub expires: Option<String>,
+    pub user: UserMetadata,
+}
+
+fn header_string(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| String::from_utf8(v.as_bytes().to_vec()).ok())
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    match HeaderValue::from_bytes(value.as_bytes()) {
+        Ok(v) => {
+            headers.insert(HeaderName::from_static(name), v);
+        }
+        Err(_) => tracing::warn!("Ignoring invalid {} value: {:?}", name, value),
+    }
+}
+
+impl ObjectMetadata {
+    /// Picks the metadata out of the headers of a PUT (or CreateMultipartUpload) request.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, S3Error> {
+        let mut user = UserMetadata::new();
+        let mut user_size = 0;
+        for name in headers.keys() {
+            let Some(key) = name.as_str().strip_prefix(USER_METADATA_PREFIX) else {
+                continue;
+            };
+            // repeated headers are combined, like S3 does
+            let mut values = Vec::new();
+            for value in headers.get_all(name) {
+                match std::str::from_utf8(value.as_bytes()) {
+                    Ok(v) => values.push(v),
+                    Err(_) => return Err(S3Error::InvalidArgument),
+                }
+            }
+            let value = values.join(",");
+            user_size += key.len() + value.len();
+            user.insert(key.to_string(), value);
+        }
+
+        if user_size > MAX_USER_METADATA_SIZE {
+            return Err(S3Error::MetadataTooLarge);
+        }
+
+        Ok(ObjectMetadata {
+            content_type: header_string(headers, "content-type"),
+            content_encoding: header_string(headers, "content-encoding"),
+            content_disposition: header_string(headers, "content-disposition"),
+            content_language: header_string(headers, "content-language"),
+            cache_control: header_string(headers, "cache-control"),
+            expires: header_string(headers, "expires"),
+            user,
+        })
+    }
+
+    /// The user metadata as bound to a `user_metadata` column; objects without any store NULL.
+    pub fn user_json(&self) -> Option<Json<&UserMetadata>> {
+        (!self.user.is_empty()).then_some(Json(&self.user))
+    }
+
+    /// Adds the metadata to the headers of a GET/HEAD response.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        insert_header(
+            headers,
+            "content-type",
+            self.content_type.as_deref().unwrap_or(DEFAULT_CONTENT_TYPE),
+        );
+        let optional = [
+            ("content-encoding", &self.content_encoding),
+            ("content-disposition", &self.content_disposition),
+            ("content-language", &self.content_language),
+            ("cache-control", &self.cache_control),
+            ("expires", &self.expires),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                insert_header(headers, name, value);
+            }
+        }
+        for (key, value) in &self.user {
+            let name =
+                HeaderName::from_bytes(format!("{}{}", USER_METADATA_PREFIX, key).as_bytes());
+            match (name, HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => tracing::warn!("Ignoring invalid user metadata: {:?}", key),
+            }
+        }
+    }
+}
+
+/// `response-*` query parameters of GetObject/HeadObject, overriding the stored metadata.
+#[derive(serde::Deserialize, Default, Debug)]
+pub struct ResponseHeaderOverrides {
+    #[serde(rename = "response-content-type")]
+    pub content_type: Option<String>,
+    #[serde(rename = "response-content-encoding")]
+    pub content_encoding: Option<String>,
+    #[serde(rename = "response-content-disposition")]
+    pub content_disposition: Option<String>,
+    #[serde(rename = "response-content-language")]
+    pub content_language: Option<String>,
+    #[serde(rename = "response-cache-control")]
+    pub cache_control: Option<String>,
+    #[serde(rename = "response-expires")]
+    pub expires: Option<String>,
+}
+
+impl ResponseHeaderOverrides {
+    /// Checks that every override can be sent as a header value.
+    pub fn validate(&self) -> Result<(), S3Error> {
+        let values = [
+            &self.content_type,
+            &self.content_encoding,
+            &self.content_disposition,
+            &self.content_language,
+            &self.cache_control,
+            &self.expires,
+        ];
+        for value in values.into_iter().flatten() {
+            if HeaderValue::from_str(value).is_err() {
+                return Err(S3Error::InvalidArgument);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        let overrides = [
+            ("content-type", &self.content_type),
+            ("content-encoding", &self.content_encoding),
+            ("content-disposition", &self.content_disposition),
+            ("content-language", &self.content_language),
+            ("cache-control", &self.cache_control),
+            ("expires", &self.expires),
+        ];
+        for (name, value) in overrides {
+            if let Some(value) = value {
+                insert_header(headers, name, value);
+            }
+        }
+    }
+}
+
+/// Formats a timestamp as an HTTP-date, e.g. for `Last-Modified`.
+pub fn format_http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
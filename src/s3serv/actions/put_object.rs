@@ -1,19 +1,56 @@
+use std::sync::Arc;
+
 use axum::{
     body::BodyDataStream,
+    http::HeaderMap,
     response::{IntoResponse, Response},
 };
+use md5::Digest;
 use reqwest::StatusCode;
-use sqlx::{postgres::types::PgRange, PgPool};
+use sqlx::PgPool;
 
-use crate::{drivers, s3serv::error::S3Error};
+use super::{
+    bucket_versioning::Versioning, conditions::WriteCondition, file_data, file_versions,
+    object_metadata::ObjectMetadata,
+};
+use crate::{
+    drivers::Drivers,
+    s3serv::{
+        checksum::{self, Checksums, ChecksumsHasher, RequestChecksum},
+        error::S3Error,
+        etag::format_etag,
+    },
+};
 
-#[tracing::instrument(skip(pool, body))]
+#[tracing::instrument(skip(pool, drivers, headers, body))]
 pub async fn put_object(
     pool: PgPool,
+    drivers: Arc<Drivers>,
     bucket: String,
     key: String,
+    headers: HeaderMap,
     body: &mut BodyDataStream,
 ) -> Response {
+    let metadata = match ObjectMetadata::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let request_checksum = match RequestChecksum::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let content_md5 = match checksum::parse_content_md5(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let condition = match WriteCondition::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
     let tx = pool.begin().await;
 
     let mut tx = match tx {
@@ -24,12 +61,15 @@ pub async fn put_object(
         }
     };
 
-    let result = sqlx::query!("SELECT id FROM buckets WHERE name = $1 LIMIT 1", bucket)
-        .fetch_one(&mut *tx)
-        .await;
+    let result = sqlx::query!(
+        "SELECT id, versioning FROM buckets WHERE name = $1 LIMIT 1",
+        bucket
+    )
+    .fetch_one(&mut *tx)
+    .await;
 
-    let bucket_id = match result {
-        Ok(v) => v.id,
+    let (bucket_id, versioning) = match result {
+        Ok(v) => (v.id, v.versioning),
         Err(e) => {
             if let sqlx::Error::RowNotFound = e {
                 return S3Error::NoSuchBucket.into_response();
@@ -39,7 +79,27 @@ pub async fn put_object(
         }
     };
 
-    let result = drivers::ton::upload_from_stream(&tx, body).await;
+    let versioning = match Versioning::from_db(&versioning) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    // fail early rather than after receiving the whole body; create_version checks again
+    if let Some(condition) = &condition {
+        let etag = match file_versions::current_etag(&mut tx, bucket_id, &key).await {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        if let Err(e) = condition.check(etag.as_deref()) {
+            return e.into_response();
+        }
+    }
+
+    // a mismatch fails the upload before anything is written, and the transaction rolls back
+    let verify = |md5: &[u8; 16], checksums: &Checksums| {
+        checksum::verify_upload(content_md5, request_checksum.as_ref(), md5, checksums)
+    };
+    let result = drivers.default_driver().upload(body, &verify).await;
 
     let result = match result {
         Err(e) => {
@@ -49,153 +109,108 @@ pub async fn put_object(
         Ok(v) => v,
     };
 
-    let file_data_id = match result {
-        None => None,
-        Some(result) => {
-            let data_id = sqlx::query!(
-                "INSERT INTO file_data(size, md5) VALUES($1, $2) RETURNING id",
-                result.size as i64,
-                &result.md5
-            )
-            .fetch_one(&mut *tx)
-            .await;
-
-            let data_id = match data_id {
-                Ok(v) => v.id,
-                Err(e) => {
-                    tracing::error!("Failed to insert file data: {:?}", e);
-                    return S3Error::InternalError.into_response();
-                }
-            };
-
-            let part_id = sqlx::query!(
-                "INSERT INTO file_data_parts(file_data_id, backend_key, range) VALUES($1, $2, $3) RETURNING id",
-                data_id,
-                result.r#ref,
-                PgRange::from(0..(result.size as i64))
-            )
-            .fetch_one(&mut *tx)
-            .await;
-
-            let part_id = match part_id {
-                Ok(rec) => rec.id,
-                Err(e) => {
-                    tracing::error!("Failed to insert file data part: {:?}", e);
-                    return S3Error::InternalError.into_response();
-                }
-            };
-
-            let mut builder = sqlx::QueryBuilder::new(
-                "INSERT INTO file_data_part_chunk_info (part_id, range, md5, sha256) ",
-            );
-
-            builder.push_values(result.chunks, |mut b, chunk| {
-                b.push_bind(part_id)
-                    .push_bind(PgRange::from(chunk.range.clone()))
-                    .push_bind(chunk.md5.clone())
-                    .push_bind(chunk.sha256.clone());
-            });
-
-            let insert_chunk = builder.build().execute(&mut *tx).await;
-
-            if let Err(e) = insert_chunk {
-                tracing::error!("Failed to insert chunk info: {:?}", e);
-                return S3Error::InternalError.into_response();
-            }
-
-            Some(data_id)
-        }
+    // empty bodies are not stored by the driver, but still get a row for their digests
+    let (size, md5, checksums) = match &result {
+        Some(result) => (
+            result.size as i64,
+            result.md5.to_vec(),
+            result.checksums.clone(),
+        ),
+        None => (
+            0,
+            md5::Md5::digest([]).to_vec(),
+            ChecksumsHasher::default().finalize(),
+        ),
     };
 
-    let result = sqlx::query!("SET CONSTRAINTS ALL DEFERRED")
-        .execute(&mut *tx)
+    let checksum_header = request_checksum.as_ref().map(|v| {
+        (
+            v.algorithm.header_name(),
+            checksum::encode_checksum(checksums.get(v.algorithm)),
+        )
+    });
+
+    // returned so that the client can make its next write conditional on this one
+    let etag = format_etag(&md5, None);
+
+    let driver = drivers.default_driver();
+    let backend_key = result.as_ref().map(|v| v.r#ref.clone());
+
+    let mut orphans = Vec::new();
+    let stored = async {
+        let data_id = sqlx::query!(
+            r#"
+            INSERT INTO file_data(size, md5, crc32, crc32c, sha1, sha256, checksum_algorithm)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+        "#,
+            size,
+            &md5,
+            &checksums.crc32,
+            &checksums.crc32c,
+            &checksums.sha1,
+            &checksums.sha256,
+            request_checksum.as_ref().map(|v| v.algorithm.name())
+        )
+        .fetch_one(&mut *tx)
         .await;
 
-    match result {
-        Ok(_) => (),
-        Err(e) => {
-            tracing::error!("Failed to set constraints deferred: {:?}", e);
-            return S3Error::InternalError.into_response();
-        }
-    }
-
-    let file_id = sqlx::query!(
-        r#"
-        INSERT INTO
-            files(bucket_id, key, current_version, current_version_is_delete_marker)
-        VALUES ($1, $2, -1, FALSE)
-        ON CONFLICT DO NOTHING RETURNING id
-    "#,
-        bucket_id,
-        key
-    )
-    .fetch_optional(&mut *tx)
-    .await;
+        let data_id = match data_id {
+            Ok(v) => v.id,
+            Err(e) => {
+                tracing::error!("Failed to insert file data: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        };
 
-    let file_id = match file_id {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("Failed to insert file: {:?}", e);
-            return S3Error::InternalError.into_response();
+        if let Some(result) = result {
+            file_data::insert_data_part(&mut tx, driver.name(), Some(data_id), None, result)
+                .await?;
         }
-    };
 
-    let file_id = match file_id {
-        Some(v) => v.id,
-        None => {
-            let file_id = sqlx::query!(
-                "SELECT id FROM files WHERE bucket_id = $1 AND key = $2 LIMIT 1 FOR UPDATE",
-                bucket_id,
-                key
-            )
-            .fetch_one(&mut *tx)
-            .await;
-
-            match file_id {
-                Ok(v) => v.id,
-                Err(e) => {
-                    tracing::error!("Failed to fetch file: {:?}", e);
-                    return S3Error::InternalError.into_response();
-                }
+        let version_id = file_versions::create_version(
+            &mut tx,
+            bucket_id,
+            &key,
+            Some(data_id),
+            false,
+            versioning,
+            &metadata,
+            condition.as_ref(),
+            &mut orphans,
+        )
+        .await?;
+
+        match tx.commit().await {
+            Ok(_) => Ok(version_id),
+            Err(e) => {
+                tracing::error!("Failed to commit transaction: {:?}", e);
+                Err(S3Error::InternalError.into_response())
             }
         }
-    };
-
-    let file_version_id = sqlx::query!(
-        "INSERT INTO file_versions(file_id, file_data_id) VALUES($1, $2) RETURNING id",
-        file_id,
-        file_data_id
-    )
-    .fetch_one(&mut *tx)
+    }
     .await;
 
-    let file_version_id = match file_version_id {
-        Ok(v) => v.id,
-        Err(e) => {
-            tracing::error!("Failed to insert file version: {:?}", e);
-            return S3Error::InternalError.into_response();
-        }
-    };
-
-    let update_file_result = sqlx::query!("UPDATE files SET current_version = $1, current_version_is_delete_marker = FALSE WHERE id = $2", file_version_id, file_id)
-        .execute(&mut *tx)
-        .await;
-
-    match update_file_result {
-        Ok(_) => (),
+    let version_id = match stored {
+        Ok(v) => v,
         Err(e) => {
-            tracing::error!("Failed to update file: {:?}", e);
-            return S3Error::InternalError.into_response();
+            // nothing references the uploaded data
+            if let Some(backend_key) = backend_key {
+                let _ = driver.delete(&backend_key).await;
+            }
+            return e;
         }
     };
 
-    match tx.commit().await {
-        Ok(_) => (),
-        Err(e) => {
-            tracing::error!("Failed to commit transaction: {:?}", e);
-            return S3Error::InternalError.into_response();
-        }
-    };
+    file_data::delete_backend_objects(&drivers, orphans).await;
 
-    StatusCode::NO_CONTENT.into_response()
+    let mut res = (StatusCode::OK, [("ETag", etag)]).into_response();
+    if versioning == Versioning::Enabled {
+        res.headers_mut()
+            .insert("x-amz-version-id", version_id.to_string().parse().unwrap());
+    }
+    if let Some((name, value)) = checksum_header {
+        res.headers_mut().insert(name, value.parse().unwrap());
+    }
+    res
 }
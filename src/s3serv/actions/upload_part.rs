@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use axum::{
+    body::BodyDataStream,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use md5::Digest;
+use sqlx::PgPool;
+
+use super::file_data;
+use crate::{
+    drivers::Drivers,
+    s3serv::{
+        checksum::{self, Checksums, ChecksumsHasher, RequestChecksum},
+        error::S3Error,
+        etag::format_etag,
+    },
+};
+
+pub fn parse_part_number(part_number: &str) -> Option<i32> {
+    part_number
+        .parse::<i32>()
+        .ok()
+        .filter(|v| (1..=10000).contains(v))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(pool, drivers, headers, body))]
+pub async fn upload_part(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part_number: String,
+    headers: HeaderMap,
+    body: &mut BodyDataStream,
+) -> Response {
+    let Some(part_number) = parse_part_number(&part_number) else {
+        return S3Error::InvalidArgument.into_response();
+    };
+
+    let request_checksum = match RequestChecksum::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let content_md5 = match checksum::parse_content_md5(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT multipart_uploads.id
+        FROM multipart_uploads
+            JOIN buckets ON buckets.id = multipart_uploads.bucket_id
+        WHERE
+            buckets.name = $1
+            AND multipart_uploads.key = $2
+            AND multipart_uploads.upload_id = $3
+    "#,
+        bucket,
+        key,
+        upload_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let multipart_upload_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchUpload.into_response();
+            }
+            tracing::error!("Failed to fetch multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let driver = drivers.default_driver();
+    // a mismatch fails the upload before the part is recorded
+    let verify = |md5: &[u8; 16], checksums: &Checksums| {
+        checksum::verify_upload(content_md5, request_checksum.as_ref(), md5, checksums)
+    };
+    let result = driver.upload(body, &verify).await;
+
+    let result = match result {
+        Err(e) => {
+            tracing::error!("Failed to upload part");
+            return e;
+        }
+        Ok(v) => v,
+    };
+
+    let checksum_header = request_checksum.as_ref().map(|v| {
+        let value = match &result {
+            Some(result) => checksum::encode_checksum(result.checksums.get(v.algorithm)),
+            None => {
+                checksum::encode_checksum(ChecksumsHasher::default().finalize().get(v.algorithm))
+            }
+        };
+        (v.algorithm.header_name(), value)
+    });
+
+    let (size, md5) = match &result {
+        Some(result) => (result.size as i64, result.md5.to_vec()),
+        None => (0, md5::Md5::digest([]).to_vec()),
+    };
+
+    let backend_key = result.as_ref().map(|v| v.r#ref.clone());
+
+    let mut orphans = Vec::new();
+    let stored = async {
+        // make sure the upload was not completed or aborted while we were receiving the body
+        let result_upload = sqlx::query!(
+            "SELECT id FROM multipart_uploads WHERE id = $1 FOR SHARE",
+            multipart_upload_id
+        )
+        .fetch_optional(&mut *tx)
+        .await;
+
+        match result_upload {
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(S3Error::NoSuchUpload.into_response()),
+            Err(e) => {
+                tracing::error!("Failed to lock multipart upload: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        }
+
+        let previous = sqlx::query!(
+            "SELECT id FROM multipart_upload_parts WHERE multipart_upload_id = $1 AND part_number = $2 FOR UPDATE",
+            multipart_upload_id,
+            part_number
+        )
+        .fetch_all(&mut *tx)
+        .await;
+
+        let previous = match previous {
+            Ok(v) => v.into_iter().map(|v| v.id).collect::<Vec<_>>(),
+            Err(e) => {
+                tracing::error!("Failed to fetch previous part: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        };
+
+        file_data::delete_multipart_upload_parts(&mut tx, &previous, &mut orphans).await?;
+
+        let part_id = sqlx::query!(
+            r#"
+            INSERT INTO multipart_upload_parts(multipart_upload_id, part_number, size, md5)
+            VALUES($1, $2, $3, $4)
+            RETURNING id
+        "#,
+            multipart_upload_id,
+            part_number,
+            size,
+            &md5
+        )
+        .fetch_one(&mut *tx)
+        .await;
+
+        let part_id = match part_id {
+            Ok(v) => v.id,
+            Err(e) => {
+                tracing::error!("Failed to insert multipart upload part: {:?}", e);
+                return Err(S3Error::InternalError.into_response());
+            }
+        };
+
+        if let Some(result) = result {
+            file_data::insert_data_part(&mut tx, driver.name(), None, Some(part_id), result)
+                .await?;
+        }
+
+        match tx.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to commit transaction: {:?}", e);
+                Err(S3Error::InternalError.into_response())
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = stored {
+        // nothing references the uploaded part
+        if let Some(backend_key) = backend_key {
+            let _ = driver.delete(&backend_key).await;
+        }
+        return e;
+    }
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let mut res = (StatusCode::OK, [("ETag", format_etag(&md5, None))]).into_response();
+    if let Some((name, value)) = checksum_header {
+        res.headers_mut().insert(name, value.parse().unwrap());
+    }
+    res
+}
@@ -0,0 +1,200 @@
+use std::{ops::Range, sync::Arc};
+
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use chrono::SecondsFormat;
+use md5::Digest;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use super::{copy_object::CopySource, file_data, upload_part::parse_part_number};
+use crate::{
+    drivers::Drivers,
+    s3serv::{error::S3Error, etag::format_etag},
+};
+
+#[derive(serde::Serialize)]
+struct CopyPartResult {
+    #[serde(rename = "LastModified")]
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+/// Parses `x-amz-copy-source-range: bytes=<first>-<last>`. The whole object is copied without it.
+fn parse_copy_source_range(headers: &HeaderMap, size: i64) -> Result<Range<i64>, S3Error> {
+    let Some(value) = headers.get("x-amz-copy-source-range") else {
+        return Ok(0..size);
+    };
+    let range = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(first, last)| Some((first.parse::<i64>().ok()?, last.parse::<i64>().ok()?)));
+    match range {
+        Some((first, last)) if first <= last && last < size => Ok(first..last + 1),
+        _ => Err(S3Error::InvalidArgument),
+    }
+}
+
+/// Creates a part of a multipart upload from a range of an existing object, without copying
+/// its data.
+///
+/// The bytes are never read, so the part's "MD5" (and ETag) is derived from the MD5 of the
+/// source and the range instead of the content.
+#[tracing::instrument(skip(pool, drivers, headers))]
+pub async fn upload_part_copy(
+    pool: PgPool,
+    drivers: Arc<Drivers>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part_number: String,
+    headers: HeaderMap,
+) -> Response {
+    let Some(part_number) = parse_part_number(&part_number) else {
+        return S3Error::InvalidArgument.into_response();
+    };
+
+    let source = match CopySource::from_headers(&headers) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let object = match source.find_object(&pool, &headers).await {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+
+    let range = match parse_copy_source_range(&headers, object.size) {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut md5_hasher = md5::Md5::new();
+    md5_hasher.update(&object.md5);
+    md5_hasher.update(range.start.to_be_bytes());
+    md5_hasher.update(range.end.to_be_bytes());
+    let md5 = md5_hasher.finalize().to_vec();
+
+    let tx = pool.begin().await;
+
+    let mut tx = match tx {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to start transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT multipart_uploads.id
+        FROM multipart_uploads
+            JOIN buckets ON buckets.id = multipart_uploads.bucket_id
+        WHERE
+            buckets.name = $1
+            AND multipart_uploads.key = $2
+            AND multipart_uploads.upload_id = $3
+        FOR SHARE OF multipart_uploads
+    "#,
+        bucket,
+        key,
+        upload_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let multipart_upload_id = match result {
+        Ok(v) => v.id,
+        Err(e) => {
+            if let sqlx::Error::RowNotFound = e {
+                return S3Error::NoSuchUpload.into_response();
+            }
+            tracing::error!("Failed to fetch multipart upload: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let previous = sqlx::query!(
+        "SELECT id FROM multipart_upload_parts WHERE multipart_upload_id = $1 AND part_number = $2 FOR UPDATE",
+        multipart_upload_id,
+        part_number
+    )
+    .fetch_all(&mut *tx)
+    .await;
+
+    let previous = match previous {
+        Ok(v) => v.into_iter().map(|v| v.id).collect::<Vec<_>>(),
+        Err(e) => {
+            tracing::error!("Failed to fetch previous part: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    let mut orphans = Vec::new();
+    if let Err(e) = file_data::delete_multipart_upload_parts(&mut tx, &previous, &mut orphans).await
+    {
+        return e;
+    }
+
+    let part = sqlx::query!(
+        r#"
+        INSERT INTO multipart_upload_parts(multipart_upload_id, part_number, size, md5)
+        VALUES($1, $2, $3, $4)
+        RETURNING id, created_at
+    "#,
+        multipart_upload_id,
+        part_number,
+        range.end - range.start,
+        md5
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    let part = match part {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("Failed to insert multipart upload part: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    if let Some(data_id) = object.data_id.filter(|_| !range.is_empty()) {
+        if let Err(e) = file_data::insert_copied_data_parts(&mut tx, part.id, data_id, range).await
+        {
+            return e;
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to commit transaction: {:?}", e);
+            return S3Error::InternalError.into_response();
+        }
+    };
+
+    file_data::delete_backend_objects(&drivers, orphans).await;
+
+    let result = CopyPartResult {
+        last_modified: part.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        etag: format_etag(&md5, None),
+    };
+
+    let mut buffer = String::new();
+    let serializer = quick_xml::se::Serializer::new(&mut buffer);
+    result.serialize(serializer).expect("Failed to serialize.");
+
+    let mut res = ([("Content-Type", "application/xml")], buffer).into_response();
+    if let Some(source_version_id) = &object.version_id {
+        res.headers_mut().insert(
+            "x-amz-copy-source-version-id",
+            source_version_id.parse().unwrap(),
+        );
+    }
+    res
+}
@@ -0,0 +1,282 @@
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    BoxError,
+};
+use bytes::BytesMut;
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+
+use super::sigv4::{self, CredentialScope};
+use crate::s3serv::{
+    checksum::{self, ChecksumAlgorithm},
+    error::S3Error,
+};
+
+/// Chunk headers and trailer lines are short, anything longer is garbage.
+const MAX_LINE_LENGTH: usize = 4096;
+
+const TRAILER_SIGNATURE: &str = "x-amz-trailer-signature";
+
+/// Verifies the signatures of a signed aws-chunked body.
+///
+/// Every signature covers the previous one, starting from the signature of the request itself.
+pub struct ChunkSigner {
+    signing_key: [u8; 32],
+    timestamp: String,
+    scope: String,
+    previous_signature: String,
+}
+
+impl ChunkSigner {
+    pub fn new(
+        signing_key: [u8; 32],
+        timestamp: &str,
+        scope: &CredentialScope,
+        seed_signature: &str,
+    ) -> Self {
+        ChunkSigner {
+            signing_key,
+            timestamp: timestamp.to_string(),
+            scope: scope.to_scope_string(),
+            previous_signature: seed_signature.to_string(),
+        }
+    }
+
+    fn verify(&mut self, kind: &str, hashes: &str, signature: &str) -> bool {
+        let string_to_sign = format!(
+            "{}-{}\n{}\n{}\n{}\n{}",
+            sigv4::ALGORITHM,
+            kind,
+            self.timestamp,
+            self.scope,
+            self.previous_signature,
+            hashes
+        );
+        if !sigv4::verify_signature(&self.signing_key, &string_to_sign, signature) {
+            return false;
+        }
+        self.previous_signature = signature.to_string();
+        true
+    }
+
+    fn verify_chunk(&mut self, data_hash: &[u8], signature: &str) -> bool {
+        let hashes = format!(
+            "{}\n{}",
+            hex::encode(Sha256::digest([])),
+            hex::encode(data_hash)
+        );
+        self.verify("PAYLOAD", &hashes, signature)
+    }
+
+    fn verify_trailer(&mut self, canonical_trailers: &str, signature: &str) -> bool {
+        let hashes = hex::encode(Sha256::digest(canonical_trailers.as_bytes()));
+        self.verify("TRAILER", &hashes, signature)
+    }
+}
+
+pub struct ChunkedOptions {
+    /// `None` for unsigned chunks.
+    pub signer: Option<ChunkSigner>,
+    /// Checksum announced in `x-amz-trailer`.
+    pub trailer: Option<ChecksumAlgorithm>,
+    /// `x-amz-decoded-content-length`
+    pub decoded_length: u64,
+}
+
+/// Reads up to the next CRLF. `None` at the end of the body.
+async fn read_line(
+    body: &mut BodyDataStream,
+    buf: &mut BytesMut,
+) -> Result<Option<String>, BoxError> {
+    loop {
+        if let Some(end) = buf.windows(2).position(|w| w == b"\r\n") {
+            let line = buf.split_to(end + 2);
+            return match std::str::from_utf8(&line[..end]) {
+                Ok(v) => Ok(Some(v.to_string())),
+                Err(_) => Err(S3Error::InvalidArgument.into()),
+            };
+        }
+        if buf.len() > MAX_LINE_LENGTH {
+            return Err(S3Error::InvalidArgument.into());
+        }
+        match body.next().await {
+            Some(Ok(v)) => buf.extend_from_slice(&v),
+            Some(Err(e)) => return Err(e.into()),
+            None if buf.is_empty() => return Ok(None),
+            None => return Err(S3Error::IncompleteBody.into()),
+        }
+    }
+}
+
+/// Parses `<hex size>[;chunk-signature=<signature>]`.
+fn parse_chunk_header(line: &str, signed: bool) -> Option<(u64, Option<&str>)> {
+    let (size, signature) = match line.split_once(';') {
+        Some((size, extension)) => (size, extension.strip_prefix("chunk-signature=")),
+        None => (line, None),
+    };
+    if signed != signature.is_some() {
+        return None;
+    }
+    Some((u64::from_str_radix(size, 16).ok()?, signature))
+}
+
+/// Decodes a `Content-Encoding: aws-chunked` body:
+///
+/// ```text
+/// <hex size>[;chunk-signature=<signature>]\r\n
+/// <data>\r\n
+/// ...
+/// 0[;chunk-signature=<signature>]\r\n
+/// [<trailer name>:<value>\r\n ...]
+/// [x-amz-trailer-signature:<signature>\r\n]
+/// \r\n
+/// ```
+///
+/// The stream fails with an [`S3Error`] when the framing, a signature, the length
+/// or the trailing checksum is wrong.
+pub fn decode(body: Body, options: ChunkedOptions) -> Body {
+    let stream = async_stream::stream! {
+        let ChunkedOptions { mut signer, trailer, decoded_length } = options;
+        let mut body = body.into_data_stream();
+        let mut buf = BytesMut::new();
+        let mut decoded = 0u64;
+        let mut checksum = trailer.map(ChecksumAlgorithm::hasher);
+
+        loop {
+            let line = match read_line(&mut body, &mut buf).await {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    yield Err(S3Error::IncompleteBody.into());
+                    return;
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let Some((size, signature)) = parse_chunk_header(&line, signer.is_some()) else {
+                tracing::debug!("Invalid chunk header: {:?}", line);
+                yield Err(S3Error::InvalidArgument.into());
+                return;
+            };
+
+            decoded += size;
+            if decoded > decoded_length {
+                yield Err(S3Error::IncompleteBody.into());
+                return;
+            }
+
+            // held back until the signature of the whole chunk is verified
+            let mut chunk = Vec::new();
+            let mut chunk_hasher = Sha256::new();
+            let mut remaining = size as usize;
+            while remaining > 0 {
+                if buf.is_empty() {
+                    match body.next().await {
+                        Some(Ok(v)) => buf.extend_from_slice(&v),
+                        Some(Err(e)) => {
+                            yield Err(e.into());
+                            return;
+                        }
+                        None => {
+                            yield Err(S3Error::IncompleteBody.into());
+                            return;
+                        }
+                    }
+                    continue;
+                }
+                let data = buf.split_to(remaining.min(buf.len())).freeze();
+                remaining -= data.len();
+                chunk_hasher.update(&data);
+                if let Some(checksum) = checksum.as_mut() {
+                    checksum.update(&data);
+                }
+                chunk.push(data);
+            }
+
+            if let (Some(signer), Some(signature)) = (signer.as_mut(), signature) {
+                if !signer.verify_chunk(&chunk_hasher.finalize(), signature) {
+                    yield Err(S3Error::SignatureDoesNotMatch.into());
+                    return;
+                }
+            }
+            for data in chunk {
+                yield Ok::<Bytes, BoxError>(data);
+            }
+
+            if size == 0 {
+                break;
+            }
+
+            match read_line(&mut body, &mut buf).await {
+                Ok(Some(v)) if v.is_empty() => (),
+                Ok(_) => {
+                    yield Err(S3Error::InvalidArgument.into());
+                    return;
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+
+        if decoded != decoded_length {
+            yield Err(S3Error::IncompleteBody.into());
+            return;
+        }
+
+        // trailers, up to an empty line (or the end of the body)
+        let mut trailers = Vec::new();
+        let mut trailer_signature = None;
+        loop {
+            let line = match read_line(&mut body, &mut buf).await {
+                Ok(Some(v)) if !v.is_empty() => v,
+                Ok(_) => break,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let Some((name, value)) = line.split_once(':') else {
+                yield Err(S3Error::InvalidArgument.into());
+                return;
+            };
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+            if name == TRAILER_SIGNATURE {
+                trailer_signature = Some(value);
+            } else {
+                trailers.push((name, value));
+            }
+        }
+
+        if let (Some(signer), Some(_)) = (signer.as_mut(), trailer) {
+            let canonical_trailers = trailers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect::<String>();
+            let verified = trailer_signature
+                .is_some_and(|signature| signer.verify_trailer(&canonical_trailers, &signature));
+            if !verified {
+                yield Err(S3Error::SignatureDoesNotMatch.into());
+                return;
+            }
+        }
+
+        if let (Some(algorithm), Some(checksum)) = (trailer, checksum) {
+            let expected = trailers
+                .iter()
+                .find(|(name, _)| ChecksumAlgorithm::from_header_name(name) == Some(algorithm))
+                .and_then(|(_, value)| checksum::decode_checksum(value));
+            let Some(expected) = expected else {
+                yield Err(S3Error::InvalidArgument.into());
+                return;
+            };
+            if checksum.finalize() != expected {
+                yield Err(S3Error::BadDigest.into());
+                return;
+            }
+        }
+    };
+    Body::from_stream(stream)
+}
@@ -0,0 +1,417 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{request::Parts, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio_stream::StreamExt;
+
+use crate::s3serv::{checksum::ChecksumAlgorithm, error::S3Error};
+
+mod chunked;
+pub mod sigv4;
+
+use chunked::{ChunkSigner, ChunkedOptions};
+use sigv4::{CanonicalRequest, CredentialScope};
+
+/// Requests further than this from the server clock are rejected.
+const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(15);
+
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const STREAMING_SIGNED_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+const STREAMING_SIGNED_PAYLOAD_TRAILER: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER";
+const STREAMING_UNSIGNED_PAYLOAD_TRAILER: &str = "STREAMING-UNSIGNED-PAYLOAD-TRAILER";
+
+/// Whether the body is aws-chunked with trailers, the only kind `x-amz-trailer` applies to.
+pub fn has_trailers(headers: &HeaderMap) -> bool {
+    headers.get("x-amz-content-sha256").is_some_and(|v| {
+        v == STREAMING_SIGNED_PAYLOAD_TRAILER || v == STREAMING_UNSIGNED_PAYLOAD_TRAILER
+    })
+}
+
+/// How the request body is covered by the signature.
+enum Payload {
+    Unsigned,
+    Sha256([u8; 32]),
+    Chunked(ChunkedOptions),
+}
+
+/// Longest validity of a presigned URL, 7 days.
+const MAX_PRESIGNED_EXPIRES: i64 = 7 * 24 * 60 * 60;
+
+/// A request signature, taken from the `Authorization` header or the query of a presigned URL.
+struct Signature {
+    access_key_id: String,
+    scope: CredentialScope,
+    timestamp: String,
+    signed_headers: String,
+    signature: String,
+}
+
+/// Splits `<access key id>/<date>/<region>/s3/aws4_request`.
+fn parse_credential(credential: &str) -> Option<(String, CredentialScope)> {
+    let (access_key_id, scope) = credential.split_once('/')?;
+    Some((access_key_id.to_string(), CredentialScope::parse(scope)?))
+}
+
+/// Parses the request timestamp, which has to be on the day of the credential scope.
+fn parse_timestamp(signature: &Signature) -> Result<NaiveDateTime, S3Error> {
+    let Ok(time) = NaiveDateTime::parse_from_str(&signature.timestamp, sigv4::TIMESTAMP_FORMAT)
+    else {
+        return Err(S3Error::AccessDenied);
+    };
+    if !signature.timestamp.starts_with(&signature.scope.date) {
+        return Err(S3Error::AuthorizationHeaderMalformed);
+    }
+    Ok(time)
+}
+
+/// Parses `Authorization: AWS4-HMAC-SHA256 Credential=..., SignedHeaders=..., Signature=...`.
+fn parse_authorization(value: &str, timestamp: &str) -> Result<Signature, S3Error> {
+    let Some(fields) = value
+        .strip_prefix(sigv4::ALGORITHM)
+        .and_then(|v| v.strip_prefix(' '))
+    else {
+        return Err(S3Error::AuthorizationHeaderMalformed);
+    };
+
+    let mut credential = None;
+    let mut signed_headers = None;
+    let mut signature = None;
+    for field in fields.split(',') {
+        let Some((name, value)) = field.trim().split_once('=') else {
+            return Err(S3Error::AuthorizationHeaderMalformed);
+        };
+        match name {
+            "Credential" => credential = Some(value),
+            "SignedHeaders" => signed_headers = Some(value),
+            "Signature" => signature = Some(value),
+            _ => return Err(S3Error::AuthorizationHeaderMalformed),
+        }
+    }
+
+    let (Some(credential), Some(signed_headers), Some(signature)) =
+        (credential, signed_headers, signature)
+    else {
+        return Err(S3Error::AuthorizationHeaderMalformed);
+    };
+    let Some((access_key_id, scope)) = parse_credential(credential) else {
+        return Err(S3Error::AuthorizationHeaderMalformed);
+    };
+
+    Ok(Signature {
+        access_key_id,
+        scope,
+        timestamp: timestamp.to_string(),
+        signed_headers: signed_headers.to_string(),
+        signature: signature.to_string(),
+    })
+}
+
+async fn fetch_secret_access_key(pool: &PgPool, access_key_id: &str) -> Result<String, S3Error> {
+    let result = sqlx::query!(
+        "SELECT secret_access_key FROM access_keys WHERE access_key_id = $1 LIMIT 1",
+        access_key_id
+    )
+    .fetch_optional(pool)
+    .await;
+
+    match result {
+        Ok(Some(v)) => Ok(v.secret_access_key),
+        Ok(None) => Err(S3Error::InvalidAccessKeyId),
+        Err(e) => {
+            tracing::error!("Failed to fetch access key: {:?}", e);
+            Err(S3Error::InternalError)
+        }
+    }
+}
+
+/// Computes the signature of the request again and compares it with the given one.
+///
+/// Every `x-amz-*` header but those in `may_be_unsigned` has to be signed, otherwise
+/// anyone could add e.g. `x-amz-copy-source` to a signed request.
+///
+/// Returns the signing key, which also signs the chunks of a streaming upload.
+async fn check_signature(
+    pool: &PgPool,
+    parts: &Parts,
+    signature: &Signature,
+    query: String,
+    payload_hash: &str,
+    may_be_unsigned: &[&str],
+) -> Result<[u8; 32], S3Error> {
+    let signed_headers = signature.signed_headers.split(';').collect::<Vec<_>>();
+    if !signed_headers.contains(&"host") {
+        return Err(S3Error::AccessDenied);
+    }
+    let unsigned = parts.headers.keys().map(|v| v.as_str()).find(|v| {
+        v.starts_with("x-amz-") && !signed_headers.contains(v) && !may_be_unsigned.contains(v)
+    });
+    if let Some(name) = unsigned {
+        tracing::debug!("{} is not signed", name);
+        return Err(S3Error::UnsignedHeaders);
+    }
+
+    let secret_access_key = fetch_secret_access_key(pool, &signature.access_key_id).await?;
+
+    let canonical_request = CanonicalRequest {
+        method: parts.method.as_str(),
+        path: parts.uri.path(),
+        query,
+        headers: sigv4::canonical_headers(&parts.headers, &signed_headers),
+        signed_headers: &signature.signed_headers,
+        payload_hash,
+    }
+    .to_canonical_string();
+    let string_to_sign =
+        sigv4::string_to_sign(&signature.timestamp, &signature.scope, &canonical_request);
+    let signing_key = signature.scope.signing_key(&secret_access_key);
+
+    if !sigv4::verify_signature(&signing_key, &string_to_sign, &signature.signature) {
+        tracing::debug!(
+            "Signature mismatch, canonical request: {:?}",
+            canonical_request
+        );
+        return Err(S3Error::SignatureDoesNotMatch);
+    }
+
+    tracing::debug!("authenticated as {}", signature.access_key_id);
+
+    Ok(signing_key)
+}
+
+/// Verifies a request signed in the `Authorization` header.
+async fn verify_header_signature(
+    pool: &PgPool,
+    parts: &Parts,
+    authorization: &str,
+) -> Result<Payload, S3Error> {
+    let Some(timestamp) = parts
+        .headers
+        .get("x-amz-date")
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Err(S3Error::AccessDenied);
+    };
+    let signature = parse_authorization(authorization, timestamp)?;
+    let time = parse_timestamp(&signature)?;
+    if (Utc::now().naive_utc() - time).abs() > MAX_CLOCK_SKEW {
+        return Err(S3Error::RequestTimeTooSkewed);
+    }
+
+    let Some(payload_hash) = parts
+        .headers
+        .get("x-amz-content-sha256")
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Err(S3Error::InvalidArgument);
+    };
+    let query = sigv4::canonical_query(parts.uri.query(), None);
+    let signing_key = check_signature(
+        pool,
+        parts,
+        &signature,
+        query,
+        payload_hash,
+        &["x-amz-content-sha256", "x-amz-date"],
+    )
+    .await?;
+
+    let payload = match payload_hash {
+        UNSIGNED_PAYLOAD => Payload::Unsigned,
+        STREAMING_SIGNED_PAYLOAD
+        | STREAMING_SIGNED_PAYLOAD_TRAILER
+        | STREAMING_UNSIGNED_PAYLOAD_TRAILER => {
+            let signer = (payload_hash != STREAMING_UNSIGNED_PAYLOAD_TRAILER).then(|| {
+                ChunkSigner::new(
+                    signing_key,
+                    &signature.timestamp,
+                    &signature.scope,
+                    &signature.signature,
+                )
+            });
+            let trailer = if payload_hash == STREAMING_SIGNED_PAYLOAD {
+                None
+            } else {
+                let trailer = parts
+                    .headers
+                    .get("x-amz-trailer")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(ChecksumAlgorithm::from_header_name);
+                match trailer {
+                    Some(v) => Some(v),
+                    None => return Err(S3Error::InvalidArgument),
+                }
+            };
+            let decoded_length = parts
+                .headers
+                .get("x-amz-decoded-content-length")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            let Some(decoded_length) = decoded_length else {
+                return Err(S3Error::MissingContentLength);
+            };
+            Payload::Chunked(ChunkedOptions {
+                signer,
+                trailer,
+                decoded_length,
+            })
+        }
+        // e.g. ECDSA signed chunks
+        v if v.starts_with("STREAMING-") => return Err(S3Error::NotImplemented),
+        v => match hex::decode(v).ok().and_then(|v| v.try_into().ok()) {
+            Some(v) => Payload::Sha256(v),
+            None => return Err(S3Error::InvalidArgument),
+        },
+    };
+
+    Ok(payload)
+}
+
+/// Verifies a presigned URL, i.e. a request signed with `X-Amz-*` query parameters.
+///
+/// Only downloading and uploading single objects can be presigned.
+async fn verify_query_signature(pool: &PgPool, parts: &Parts) -> Result<Payload, S3Error> {
+    let query = parts.uri.query();
+    let param = |name| sigv4::query_param(query, name);
+
+    let is_object = parts
+        .uri
+        .path()
+        .trim_start_matches('/')
+        .split_once('/')
+        .is_some_and(|(_, key)| !key.is_empty());
+    if !is_object || !matches!(parts.method, Method::GET | Method::HEAD | Method::PUT) {
+        return Err(S3Error::AccessDenied);
+    }
+
+    if param("X-Amz-Algorithm").as_deref() != Some(sigv4::ALGORITHM) {
+        return Err(S3Error::AuthorizationQueryParametersError);
+    }
+    let (Some(credential), Some(timestamp), Some(expires), Some(signed_headers), Some(signature)) = (
+        param("X-Amz-Credential"),
+        param("X-Amz-Date"),
+        param("X-Amz-Expires"),
+        param("X-Amz-SignedHeaders"),
+        param("X-Amz-Signature"),
+    ) else {
+        return Err(S3Error::AuthorizationQueryParametersError);
+    };
+    let Some((access_key_id, scope)) = parse_credential(&credential) else {
+        return Err(S3Error::AuthorizationQueryParametersError);
+    };
+    let expires = match expires.parse::<i64>() {
+        Ok(v) if (1..=MAX_PRESIGNED_EXPIRES).contains(&v) => TimeDelta::seconds(v),
+        _ => return Err(S3Error::AuthorizationQueryParametersError),
+    };
+
+    let signature = Signature {
+        access_key_id,
+        scope,
+        timestamp,
+        signed_headers,
+        signature,
+    };
+    let time = parse_timestamp(&signature)?;
+    let now = Utc::now().naive_utc();
+    if time - now > MAX_CLOCK_SKEW {
+        return Err(S3Error::RequestTimeTooSkewed);
+    }
+    if now > time + expires {
+        return Err(S3Error::RequestExpired);
+    }
+
+    // the body of a presigned upload can't be known when the URL is signed
+    let query = sigv4::canonical_query(query, Some("X-Amz-Signature"));
+    check_signature(pool, parts, &signature, query, UNSIGNED_PAYLOAD, &[]).await?;
+
+    Ok(Payload::Unsigned)
+}
+
+/// Verifies the SigV4 signature of a request.
+async fn verify_request(pool: &PgPool, parts: &Parts) -> Result<Payload, S3Error> {
+    if let Some(authorization) = parts.headers.get("authorization") {
+        let Ok(authorization) = authorization.to_str() else {
+            return Err(S3Error::AuthorizationHeaderMalformed);
+        };
+        return verify_header_signature(pool, parts, authorization).await;
+    }
+
+    if sigv4::query_param(parts.uri.query(), "X-Amz-Signature").is_some() {
+        return verify_query_signature(pool, parts).await;
+    }
+
+    Err(S3Error::AccessDenied)
+}
+
+/// Fails the body stream at the end if its SHA-256 is not `expected`.
+fn verify_payload_hash(body: Body, expected: [u8; 32]) -> Body {
+    let stream = async_stream::stream! {
+        let mut body = body.into_data_stream();
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(v) => {
+                    hasher.update(&v);
+                    yield Ok::<Bytes, BoxError>(v);
+                }
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
+                }
+            }
+        }
+        if hasher.finalize()[..] != expected {
+            yield Err(S3Error::XAmzContentSHA256Mismatch.into());
+        }
+    };
+    Body::from_stream(stream)
+}
+
+/// Drops `aws-chunked` from `Content-Encoding` and sets the decoded `Content-Length`,
+/// so handlers see the request as if it was sent in one piece.
+fn strip_chunked_encoding(parts: &mut Parts, decoded_length: u64) {
+    let encodings = parts
+        .headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && *v != "aws-chunked")
+        .collect::<Vec<_>>()
+        .join(",");
+    match HeaderValue::from_str(&encodings) {
+        Ok(v) if !encodings.is_empty() => {
+            parts.headers.insert("content-encoding", v);
+        }
+        _ => {
+            parts.headers.remove("content-encoding");
+        }
+    }
+    parts
+        .headers
+        .insert("content-length", HeaderValue::from(decoded_length));
+}
+
+/// Middleware rejecting every request without a valid signature.
+pub async fn authenticate(State(pool): State<PgPool>, request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let body = match verify_request(&pool, &parts).await {
+        Ok(Payload::Unsigned) => body,
+        Ok(Payload::Sha256(expected)) => verify_payload_hash(body, expected),
+        Ok(Payload::Chunked(options)) => {
+            strip_chunked_encoding(&mut parts, options.decoded_length);
+            chunked::decode(body, options)
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    next.run(Request::from_parts(parts, body)).await
+}
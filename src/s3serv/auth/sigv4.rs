@@ -0,0 +1,180 @@
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+pub const SERVICE: &str = "s3";
+pub const TERMINATOR: &str = "aws4_request";
+
+/// Format of `x-amz-date` (and the timestamp in the string to sign).
+pub const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Everything but the unreserved characters is percent-encoded.
+const URI_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// `<date>/<region>/s3/aws4_request`, the scope a signature is valid for.
+pub struct CredentialScope {
+    pub date: String,
+    pub region: String,
+}
+
+impl CredentialScope {
+    /// Parses the part after the access key id of a `Credential`.
+    pub fn parse(scope: &str) -> Option<Self> {
+        let mut parts = scope.split('/');
+        let date = parts.next()?;
+        let region = parts.next()?;
+        let service = parts.next()?;
+        let terminator = parts.next()?;
+        if parts.next().is_some() || service != SERVICE || terminator != TERMINATOR {
+            return None;
+        }
+        Some(CredentialScope {
+            date: date.to_string(),
+            region: region.to_string(),
+        })
+    }
+
+    pub fn to_scope_string(&self) -> String {
+        format!("{}/{}/{}/{}", self.date, self.region, SERVICE, TERMINATOR)
+    }
+
+    pub fn signing_key(&self, secret_access_key: &str) -> [u8; 32] {
+        let key = hmac_sha256(
+            format!("AWS4{}", secret_access_key).as_bytes(),
+            self.date.as_bytes(),
+        );
+        let key = hmac_sha256(&key, self.region.as_bytes());
+        let key = hmac_sha256(&key, SERVICE.as_bytes());
+        hmac_sha256(&key, TERMINATOR.as_bytes())
+    }
+}
+
+fn uri_encode(value: &[u8]) -> String {
+    percent_encoding::percent_encode(value, URI_ENCODE_SET).to_string()
+}
+
+/// The path as S3 canonicalizes it: every segment is encoded once, `/` is kept.
+pub fn canonical_uri(path: &str) -> String {
+    path.split('/')
+        .map(|segment| uri_encode(&percent_decode_str(segment).collect::<Vec<u8>>()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The query string sorted by name, with names and values re-encoded.
+///
+/// `exclude` drops a parameter, i.e. the signature of a presigned URL.
+pub fn canonical_query(query: Option<&str>, exclude: Option<&str>) -> String {
+    let mut pairs = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let name = percent_decode_str(name).collect::<Vec<u8>>();
+            let value = percent_decode_str(value).collect::<Vec<u8>>();
+            (name, value)
+        })
+        .filter(|(name, _)| Some(name.as_slice()) != exclude.map(str::as_bytes))
+        .map(|(name, value)| (uri_encode(&name), uri_encode(&value)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// `name:value\n` for every signed header, values trimmed and with inner spaces collapsed.
+pub fn canonical_headers(headers: &HeaderMap, signed_headers: &[&str]) -> String {
+    let mut canonical = String::new();
+    for name in signed_headers {
+        let value = headers
+            .get_all(*name)
+            .iter()
+            .map(|v| {
+                String::from_utf8_lossy(v.as_bytes())
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        canonical.push_str(name);
+        canonical.push(':');
+        canonical.push_str(&value);
+        canonical.push('\n');
+    }
+    canonical
+}
+
+pub struct CanonicalRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: String,
+    pub headers: String,
+    pub signed_headers: &'a str,
+    pub payload_hash: &'a str,
+}
+
+impl CanonicalRequest<'_> {
+    pub fn to_canonical_string(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            self.method,
+            canonical_uri(self.path),
+            self.query,
+            self.headers,
+            self.signed_headers,
+            self.payload_hash
+        )
+    }
+}
+
+pub fn string_to_sign(timestamp: &str, scope: &CredentialScope, canonical_request: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        timestamp,
+        scope.to_scope_string(),
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    )
+}
+
+/// Hex encoded signature of `string_to_sign`, for signing requests to other S3 servers.
+pub fn sign(signing_key: &[u8; 32], string_to_sign: &str) -> String {
+    hex::encode(hmac_sha256(signing_key, string_to_sign.as_bytes()))
+}
+
+/// Checks a hex encoded signature of `string_to_sign`, in constant time.
+pub fn verify_signature(signing_key: &[u8; 32], string_to_sign: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(signing_key).expect("HMAC accepts any key length");
+    mac.update(string_to_sign.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Percent-decoded value of a query parameter.
+pub fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == name).then(|| percent_decode_str(v).decode_utf8_lossy().into_owned())
+    })
+}
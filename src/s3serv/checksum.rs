@@ -0,0 +1,293 @@
+use axum::http::HeaderMap;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::s3serv::{auth, error::S3Error};
+
+static CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+static CRC32C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+/// Algorithms of the `x-amz-checksum-*` headers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// Parses a header name like `x-amz-checksum-crc32c`.
+    pub fn from_header_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "x-amz-checksum-crc32" => Some(ChecksumAlgorithm::Crc32),
+            "x-amz-checksum-crc32c" => Some(ChecksumAlgorithm::Crc32c),
+            "x-amz-checksum-sha1" => Some(ChecksumAlgorithm::Sha1),
+            "x-amz-checksum-sha256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Parses a name like `CRC32C`, as sent in `x-amz-sdk-checksum-algorithm`
+    /// and stored in `file_data.checksum_algorithm`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "CRC32" => Some(ChecksumAlgorithm::Crc32),
+            "CRC32C" => Some(ChecksumAlgorithm::Crc32c),
+            "SHA1" => Some(ChecksumAlgorithm::Sha1),
+            "SHA256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "CRC32",
+            ChecksumAlgorithm::Crc32c => "CRC32C",
+            ChecksumAlgorithm::Sha1 => "SHA1",
+            ChecksumAlgorithm::Sha256 => "SHA256",
+        }
+    }
+
+    pub fn header_name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            ChecksumAlgorithm::Sha1 => "x-amz-checksum-sha1",
+            ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    /// Length of the raw checksum in bytes.
+    fn len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::Sha1 => 20,
+            ChecksumAlgorithm::Sha256 => 32,
+        }
+    }
+
+    pub fn hasher(self) -> Checksummer {
+        match self {
+            ChecksumAlgorithm::Crc32 => Checksummer::Crc32(CRC32.digest()),
+            ChecksumAlgorithm::Crc32c => Checksummer::Crc32c(CRC32C.digest()),
+            ChecksumAlgorithm::Sha1 => Checksummer::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Checksummer::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// Incremental computation of a checksum.
+pub enum Checksummer {
+    Crc32(crc::Digest<'static, u32>),
+    Crc32c(crc::Digest<'static, u32>),
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Checksummer {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksummer::Crc32(v) | Checksummer::Crc32c(v) => v.update(data),
+            Checksummer::Sha1(v) => v.update(data),
+            Checksummer::Sha256(v) => v.update(data),
+        }
+    }
+
+    /// Returns the raw checksum (CRCs in big-endian).
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Checksummer::Crc32(v) | Checksummer::Crc32c(v) => v.finalize().to_be_bytes().to_vec(),
+            Checksummer::Sha1(v) => v.finalize().to_vec(),
+            Checksummer::Sha256(v) => v.finalize().to_vec(),
+        }
+    }
+}
+
+/// Every supported checksum of an object, computed while it is uploaded.
+#[derive(Clone)]
+pub struct Checksums {
+    pub crc32: Vec<u8>,
+    pub crc32c: Vec<u8>,
+    pub sha1: Vec<u8>,
+    pub sha256: Vec<u8>,
+}
+
+impl Checksums {
+    pub fn get(&self, algorithm: ChecksumAlgorithm) -> &[u8] {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => &self.crc32,
+            ChecksumAlgorithm::Crc32c => &self.crc32c,
+            ChecksumAlgorithm::Sha1 => &self.sha1,
+            ChecksumAlgorithm::Sha256 => &self.sha256,
+        }
+    }
+}
+
+/// Computes all the [`Checksums`] in one pass, whichever one the client picks.
+pub struct ChecksumsHasher {
+    crc32: Checksummer,
+    crc32c: Checksummer,
+    sha1: Checksummer,
+    sha256: Checksummer,
+}
+
+impl Default for ChecksumsHasher {
+    fn default() -> Self {
+        ChecksumsHasher {
+            crc32: ChecksumAlgorithm::Crc32.hasher(),
+            crc32c: ChecksumAlgorithm::Crc32c.hasher(),
+            sha1: ChecksumAlgorithm::Sha1.hasher(),
+            sha256: ChecksumAlgorithm::Sha256.hasher(),
+        }
+    }
+}
+
+impl ChecksumsHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.crc32c.update(data);
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    pub fn finalize(self) -> Checksums {
+        Checksums {
+            crc32: self.crc32.finalize(),
+            crc32c: self.crc32c.finalize(),
+            sha1: self.sha1.finalize(),
+            sha256: self.sha256.finalize(),
+        }
+    }
+}
+
+/// The checksum a client asked for on upload.
+pub struct RequestChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Value of the `x-amz-checksum-*` header. `None` when the checksum comes as a trailer
+    /// (already verified while decoding the body) or is only requested to be computed.
+    pub expected: Option<Vec<u8>>,
+}
+
+impl RequestChecksum {
+    /// Reads `x-amz-checksum-*`, `x-amz-sdk-checksum-algorithm` and `x-amz-trailer`.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, S3Error> {
+        let mut given = None;
+        for (name, value) in headers {
+            let Some(algorithm) = ChecksumAlgorithm::from_header_name(name.as_str()) else {
+                continue;
+            };
+            if given.is_some() {
+                // only one checksum per request
+                return Err(S3Error::InvalidArgument);
+            }
+            let value = value
+                .to_str()
+                .ok()
+                .and_then(decode_checksum)
+                .filter(|v| v.len() == algorithm.len());
+            match value {
+                Some(v) => given = Some((algorithm, v)),
+                None => return Err(S3Error::InvalidArgument),
+            }
+        }
+
+        let trailer = match headers.get("x-amz-trailer") {
+            // nothing would deliver the announced checksum
+            Some(_) if !auth::has_trailers(headers) => return Err(S3Error::InvalidArgument),
+            Some(v) => match v
+                .to_str()
+                .ok()
+                .and_then(ChecksumAlgorithm::from_header_name)
+            {
+                Some(v) => Some(v),
+                None => return Err(S3Error::InvalidArgument),
+            },
+            None => None,
+        };
+
+        let requested = match headers.get("x-amz-sdk-checksum-algorithm") {
+            Some(v) => match v.to_str().ok().and_then(ChecksumAlgorithm::from_name) {
+                Some(v) => Some(v),
+                None => return Err(S3Error::InvalidArgument),
+            },
+            None => None,
+        };
+
+        let checksum = match (given, trailer) {
+            (Some(_), Some(_)) => return Err(S3Error::InvalidArgument),
+            (Some((algorithm, expected)), None) => RequestChecksum {
+                algorithm,
+                expected: Some(expected),
+            },
+            (None, Some(algorithm)) => RequestChecksum {
+                algorithm,
+                expected: None,
+            },
+            (None, None) => match requested {
+                Some(algorithm) => RequestChecksum {
+                    algorithm,
+                    expected: None,
+                },
+                None => return Ok(None),
+            },
+        };
+
+        if requested.is_some_and(|v| v != checksum.algorithm) {
+            return Err(S3Error::InvalidArgument);
+        }
+
+        Ok(Some(checksum))
+    }
+
+    pub fn verify(&self, checksums: &Checksums) -> Result<(), S3Error> {
+        match &self.expected {
+            Some(expected) if expected.as_slice() != checksums.get(self.algorithm) => {
+                Err(S3Error::BadDigest)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads the `Content-MD5` header, a base64 encoded MD5 of the body.
+pub fn parse_content_md5(headers: &HeaderMap) -> Result<Option<[u8; 16]>, S3Error> {
+    let Some(value) = headers.get("content-md5") else {
+        return Ok(None);
+    };
+    let md5 = value
+        .to_str()
+        .ok()
+        .and_then(decode_checksum)
+        .and_then(|v| v.try_into().ok());
+    match md5 {
+        Some(v) => Ok(Some(v)),
+        None => Err(S3Error::InvalidDigest),
+    }
+}
+
+/// Checks an uploaded body against its `Content-MD5` and `x-amz-checksum-*` headers.
+pub fn verify_upload(
+    content_md5: Option<[u8; 16]>,
+    request_checksum: Option<&RequestChecksum>,
+    md5: &[u8; 16],
+    checksums: &Checksums,
+) -> Result<(), S3Error> {
+    if content_md5.is_some_and(|v| &v != md5) {
+        return Err(S3Error::BadDigest);
+    }
+    match request_checksum {
+        Some(request_checksum) => request_checksum.verify(checksums),
+        None => Ok(()),
+    }
+}
+
+/// Decodes a base64 checksum as sent in `x-amz-checksum-*` headers.
+pub fn decode_checksum(value: &str) -> Option<Vec<u8>> {
+    BASE64_STANDARD.decode(value.trim()).ok()
+}
+
+pub fn encode_checksum(value: &[u8]) -> String {
+    BASE64_STANDARD.encode(value)
+}
@@ -1,39 +1,166 @@
 use axum::{http::StatusCode, response::IntoResponse};
 use serde::Serialize;
 
-#[derive(strum::IntoStaticStr)]
+#[derive(strum::IntoStaticStr, Debug)]
 pub enum S3Error {
     AccessDenied,
     InternalError,
     NotImplemented,
+    InvalidArgument,
+    MalformedXML,
+    InvalidRequest,
+    PreconditionFailed,
+    ConditionalRequestConflict,
+    // authentication
+    InvalidAccessKeyId,
+    SignatureDoesNotMatch,
+    RequestTimeTooSkewed,
+    AuthorizationHeaderMalformed,
+    XAmzContentSHA256Mismatch,
+    AuthorizationQueryParametersError,
+    #[strum(serialize = "AccessDenied")]
+    RequestExpired,
+    #[strum(serialize = "AccessDenied")]
+    UnsignedHeaders,
     // ---
     NoSuchBucket,
     NoSuchKey,
+    NoSuchVersion,
+    MethodNotAllowed,
+    // get object
+    InvalidRange,
+    // multipart upload
+    NoSuchUpload,
+    InvalidPart,
+    InvalidPartOrder,
+    EntityTooSmall,
+    // put object
+    MetadataTooLarge,
+    MissingContentLength,
+    IncompleteBody,
+    BadDigest,
+    InvalidDigest,
     // create bucket
     BucketAlreadyExists,
     BucketAlreadyOwnedByYou,
+    // delete objects
+    KeyTooLongError,
+    UserKeyMustBeSpecified,
 }
 
+impl S3Error {
+    pub fn message(&self) -> &'static str {
+        match self {
+            S3Error::AccessDenied => "Access Denied",
+            S3Error::InternalError => "Server encounted an internal error",
+            S3Error::NotImplemented => "Currently this feature is not implemented",
+            S3Error::InvalidArgument => "Invalid Argument",
+            S3Error::MalformedXML => "The XML you provided was not well-formed or did not validate against our published schema",
+            S3Error::InvalidRequest => "The request is not valid with the current state of the resource",
+            S3Error::PreconditionFailed => "At least one of the preconditions you specified did not hold",
+            S3Error::ConditionalRequestConflict => "A conflicting operation occurred. If using PutObject you can retry the request",
+            S3Error::InvalidAccessKeyId => "The AWS access key ID you provided does not exist in our records",
+            S3Error::SignatureDoesNotMatch => "The request signature we calculated does not match the signature you provided",
+            S3Error::RequestTimeTooSkewed => "The difference between the request time and the server's time is too large",
+            S3Error::AuthorizationHeaderMalformed => "The authorization header you provided is not valid",
+            S3Error::XAmzContentSHA256Mismatch => "The provided 'x-amz-content-sha256' header does not match what was computed",
+            S3Error::AuthorizationQueryParametersError => "The query-string authentication parameters are not valid",
+            S3Error::RequestExpired => "Request has expired",
+            S3Error::UnsignedHeaders => {
+                "There were headers present in the request which were not signed"
+            }
+            S3Error::BucketAlreadyExists => "Bucket already exists",
+            S3Error::BucketAlreadyOwnedByYou => "Bucket already owned by you",
+            S3Error::NoSuchBucket => "The specified bucket does not exist",
+            S3Error::NoSuchKey => "The specified key does not exist",
+            S3Error::NoSuchVersion => "The specified version does not exist",
+            S3Error::MethodNotAllowed => "The specified method is not allowed against this resource",
+            S3Error::InvalidRange => "The requested range is not satisfiable",
+            S3Error::NoSuchUpload => "The specified multipart upload does not exist",
+            S3Error::InvalidPart => "One or more of the specified parts could not be found",
+            S3Error::InvalidPartOrder => "The list of parts was not in ascending order",
+            S3Error::EntityTooSmall => "Your proposed upload is smaller than the minimum allowed object size",
+            S3Error::KeyTooLongError => "Your key is too long",
+            S3Error::UserKeyMustBeSpecified => "The bucket POST must contain the specified field name. If it is specified, check the order of the fields.",
+            S3Error::MetadataTooLarge => "Your metadata headers exceed the maximum allowed metadata size",
+            S3Error::MissingContentLength => "You must provide the Content-Length HTTP header",
+            S3Error::IncompleteBody => "You did not provide the number of bytes specified by the Content-Length HTTP header",
+            S3Error::BadDigest => "The Content-MD5 or checksum value you specified did not match what we received",
+            S3Error::InvalidDigest => "The Content-MD5 you specified is not valid",
+        }
+    }
+
+    /// Maps an error that came out of a request body.
+    ///
+    /// Body wrappers (e.g. payload signature checks) fail the stream with an `S3Error`,
+    /// which is passed through; anything else is an internal error.
+    pub fn from_body_error(error: axum::Error) -> S3Error {
+        // bodies built from streams wrap the stream error once more
+        let mut error = error.into_inner();
+        loop {
+            error = match error.downcast::<S3Error>() {
+                Ok(e) => return *e,
+                Err(e) => e,
+            };
+            error = match error.downcast::<axum::Error>() {
+                Ok(e) => e.into_inner(),
+                Err(e) => {
+                    tracing::error!("Failed to read request body: {:?}", e);
+                    return S3Error::InternalError;
+                }
+            };
+        }
+    }
+}
+
+impl std::fmt::Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code: &'static str = self.into();
+        write!(f, "{}: {}", code, self.message())
+    }
+}
+
+impl std::error::Error for S3Error {}
+
 impl IntoResponse for S3Error {
     fn into_response(self) -> axum::response::Response {
         let status = match self {
             S3Error::AccessDenied => StatusCode::FORBIDDEN,
             S3Error::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             S3Error::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            S3Error::InvalidArgument => StatusCode::BAD_REQUEST,
+            S3Error::MalformedXML => StatusCode::BAD_REQUEST,
+            S3Error::InvalidRequest => StatusCode::BAD_REQUEST,
+            S3Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            S3Error::ConditionalRequestConflict => StatusCode::CONFLICT,
+            S3Error::InvalidAccessKeyId => StatusCode::FORBIDDEN,
+            S3Error::SignatureDoesNotMatch => StatusCode::FORBIDDEN,
+            S3Error::RequestTimeTooSkewed => StatusCode::FORBIDDEN,
+            S3Error::AuthorizationHeaderMalformed => StatusCode::BAD_REQUEST,
+            S3Error::XAmzContentSHA256Mismatch => StatusCode::BAD_REQUEST,
+            S3Error::AuthorizationQueryParametersError => StatusCode::BAD_REQUEST,
+            S3Error::RequestExpired => StatusCode::FORBIDDEN,
+            S3Error::UnsignedHeaders => StatusCode::FORBIDDEN,
             S3Error::NoSuchBucket => StatusCode::NOT_FOUND,
             S3Error::NoSuchKey => StatusCode::NOT_FOUND,
+            S3Error::NoSuchVersion => StatusCode::NOT_FOUND,
+            S3Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            S3Error::InvalidRange => StatusCode::RANGE_NOT_SATISFIABLE,
+            S3Error::NoSuchUpload => StatusCode::NOT_FOUND,
+            S3Error::InvalidPart => StatusCode::BAD_REQUEST,
+            S3Error::InvalidPartOrder => StatusCode::BAD_REQUEST,
+            S3Error::EntityTooSmall => StatusCode::BAD_REQUEST,
             S3Error::BucketAlreadyExists => StatusCode::CONFLICT,
             S3Error::BucketAlreadyOwnedByYou => StatusCode::CONFLICT,
+            S3Error::KeyTooLongError => StatusCode::BAD_REQUEST,
+            S3Error::UserKeyMustBeSpecified => StatusCode::BAD_REQUEST,
+            S3Error::MetadataTooLarge => StatusCode::BAD_REQUEST,
+            S3Error::MissingContentLength => StatusCode::LENGTH_REQUIRED,
+            S3Error::IncompleteBody => StatusCode::BAD_REQUEST,
+            S3Error::BadDigest => StatusCode::BAD_REQUEST,
+            S3Error::InvalidDigest => StatusCode::BAD_REQUEST,
         };
-        let description = match self {
-            S3Error::AccessDenied => "Access Denied",
-            S3Error::InternalError => "Server encounted an internal error",
-            S3Error::NotImplemented => "Currently this feature is not implemented",
-            S3Error::BucketAlreadyExists => "Bucket already exists",
-            S3Error::BucketAlreadyOwnedByYou => "Bucket already owned by you",
-            S3Error::NoSuchBucket => "The specified bucket does not exist",
-            S3Error::NoSuchKey => "The specified key does not exist",
-        };
+        let description = self.message();
 
         let mut buffer = String::new();
         let serializer = quick_xml::se::Serializer::new(&mut buffer);
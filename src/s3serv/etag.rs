@@ -0,0 +1,10 @@
+/// Formats an ETag header value (with quotes) from the stored MD5.
+///
+/// Objects assembled by CompleteMultipartUpload store the MD5 of the concatenated
+/// part MD5s, and get the S3-style `-N` suffix.
+pub fn format_etag(md5: &[u8], multipart_parts_count: Option<i32>) -> String {
+    match multipart_parts_count {
+        Some(count) => format!("\"{}-{}\"", hex::encode(md5), count),
+        None => format!("\"{}\"", hex::encode(md5)),
+    }
+}
@@ -1,34 +1,59 @@
-use axum::routing::get;
+use std::sync::Arc;
+
+use axum::{extract::FromRef, routing::get};
 use sqlx::PgPool;
 
+use crate::drivers::Drivers;
+
 mod actions;
+pub mod auth;
+pub mod checksum;
 pub mod error;
+mod etag;
 mod routes;
 
-pub async fn start_serv(pool: PgPool) {
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub drivers: Arc<Drivers>,
+}
+
+pub fn router(pool: PgPool, drivers: Drivers) -> axum::Router {
+    let state = AppState {
+        pool: pool.clone(),
+        drivers: Arc::new(drivers),
+    };
+
     let app = axum::Router::new()
         .route("/", get(routes::get_top))
         .route("/{bucket}", routes::bucket_top())
         .route("/{bucket}/", routes::bucket_top())
         .route("/{bucket}/{*key}", routes::bucket_object());
 
-    let app = app
-        .layer(
-            tower_http::trace::TraceLayer::new_for_http().make_span_with(
-                |r: &axum::http::Request<_>| {
-                    let request_id = r
-                        .headers()
-                        .get("x-request-id")
-                        .and_then(|x| x.to_str().ok())
-                        .map(|x| x.to_string())
-                        .unwrap_or_default();
-                    tracing::info_span!("request", request_id = %request_id)
-                },
-            ),
-        )
-        .with_state(pool);
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    tracing::info!("listening on 3000 port");
+    app.layer(axum::middleware::from_fn_with_state(
+        pool.clone(),
+        auth::authenticate,
+    ))
+    .layer(
+        tower_http::trace::TraceLayer::new_for_http().make_span_with(
+            |r: &axum::http::Request<_>| {
+                let request_id = r
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|x| x.to_str().ok())
+                    .map(|x| x.to_string())
+                    .unwrap_or_default();
+                tracing::info_span!("request", request_id = %request_id)
+            },
+        ),
+    )
+    .with_state(state)
+}
+
+pub async fn start_serv(pool: PgPool, drivers: Drivers, addr: &str) {
+    let app = router(pool, drivers);
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tracing::info!("listening on {}", addr);
     axum::serve(listener, app).await.unwrap();
 }
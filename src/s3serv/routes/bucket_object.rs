@@ -1,44 +1,211 @@
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::HeaderMap,
     response::Response,
 };
+use std::sync::Arc;
+
 use sqlx::PgPool;
 
-use crate::s3serv::actions;
+use crate::{
+    drivers::Drivers,
+    s3serv::{actions, AppState},
+};
 
-pub fn bucket_object() -> axum::routing::MethodRouter<PgPool> {
+pub fn bucket_object() -> axum::routing::MethodRouter<AppState> {
     axum::routing::head(head_bucket_object)
         .get(get_bucket_object)
         .put(put_bucket_object)
+        .post(post_bucket_object)
+        .delete(delete_bucket_object)
+}
+
+#[derive(serde::Deserialize)]
+pub struct HeadObjectQuery {
+    #[serde(rename = "versionId")]
+    version_id: Option<String>,
+    #[serde(flatten)]
+    overrides: actions::ResponseHeaderOverrides,
 }
 
 pub async fn head_bucket_object(
     Path((bucket, key)): Path<(String, String)>,
     State(pool): State<PgPool>,
+    Query(query): Query<HeadObjectQuery>,
+    headers: HeaderMap,
 ) -> Response {
     tracing::debug!("bucket: {}, key: {}", bucket, key);
 
-    actions::head_object(pool, bucket, key).await
+    actions::head_object(
+        pool,
+        bucket,
+        key,
+        query.version_id,
+        query.overrides,
+        headers,
+    )
+    .await
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum GetObjectQuery {
+    ListParts {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+        #[serde(rename = "max-parts")]
+        max_parts: Option<String>,
+        #[serde(rename = "part-number-marker")]
+        part_number_marker: Option<String>,
+    },
+    GetObject {
+        #[serde(rename = "versionId")]
+        version_id: Option<String>,
+        #[serde(flatten)]
+        overrides: actions::ResponseHeaderOverrides,
+    },
 }
 
 pub async fn get_bucket_object(
     Path((bucket, key)): Path<(String, String)>,
     State(pool): State<PgPool>,
+    State(drivers): State<Arc<Drivers>>,
+    Query(query): Query<GetObjectQuery>,
+    headers: HeaderMap,
 ) -> Response {
     tracing::debug!("bucket: {}, key: {}", bucket, key);
 
-    actions::get_object(pool, bucket, key).await
+    match query {
+        GetObjectQuery::ListParts {
+            upload_id,
+            max_parts,
+            part_number_marker,
+        } => actions::list_parts(pool, bucket, key, upload_id, max_parts, part_number_marker).await,
+        GetObjectQuery::GetObject {
+            version_id,
+            overrides,
+        } => actions::get_object(pool, drivers, bucket, key, version_id, overrides, headers).await,
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum PutObjectQuery {
+    UploadPart {
+        #[serde(rename = "partNumber")]
+        part_number: String,
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+    },
+    PutObject {},
 }
 
 pub async fn put_bucket_object(
     Path((bucket, key)): Path<(String, String)>,
     State(pool): State<PgPool>,
+    State(drivers): State<Arc<Drivers>>,
+    Query(query): Query<PutObjectQuery>,
+    headers: HeaderMap,
     body: Body,
 ) -> Response {
     tracing::debug!("bucket: {}, key: {}", bucket, key);
 
     let mut body = body.into_data_stream();
 
-    actions::put_object(pool, bucket, key, &mut body).await
+    match query {
+        PutObjectQuery::UploadPart {
+            part_number,
+            upload_id,
+        } if headers.contains_key("x-amz-copy-source") => {
+            actions::upload_part_copy(pool, drivers, bucket, key, upload_id, part_number, headers)
+                .await
+        }
+        PutObjectQuery::UploadPart {
+            part_number,
+            upload_id,
+        } => {
+            actions::upload_part(
+                pool,
+                drivers,
+                bucket,
+                key,
+                upload_id,
+                part_number,
+                headers,
+                &mut body,
+            )
+            .await
+        }
+        PutObjectQuery::PutObject {} if headers.contains_key("x-amz-copy-source") => {
+            actions::copy_object(pool, drivers, bucket, key, headers).await
+        }
+        PutObjectQuery::PutObject {} => {
+            actions::put_object(pool, drivers, bucket, key, headers, &mut body).await
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum PostObjectQuery {
+    CreateMultipartUpload {
+        #[allow(dead_code)]
+        uploads: String,
+    },
+    CompleteMultipartUpload {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+    },
+}
+
+pub async fn post_bucket_object(
+    Path((bucket, key)): Path<(String, String)>,
+    State(pool): State<PgPool>,
+    State(drivers): State<Arc<Drivers>>,
+    Query(query): Query<PostObjectQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    tracing::debug!("bucket: {}, key: {}", bucket, key);
+
+    match query {
+        PostObjectQuery::CreateMultipartUpload { uploads: _ } => {
+            actions::create_multipart_upload(pool, bucket, key, headers).await
+        }
+        PostObjectQuery::CompleteMultipartUpload { upload_id } => {
+            actions::complete_multipart_upload(pool, drivers, bucket, key, upload_id, body).await
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum DeleteObjectQuery {
+    AbortMultipartUpload {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+    },
+    DeleteObject {
+        #[serde(rename = "versionId")]
+        version_id: Option<String>,
+    },
+}
+
+pub async fn delete_bucket_object(
+    Path((bucket, key)): Path<(String, String)>,
+    State(pool): State<PgPool>,
+    State(drivers): State<Arc<Drivers>>,
+    Query(query): Query<DeleteObjectQuery>,
+) -> Response {
+    tracing::debug!("bucket: {}, key: {}", bucket, key);
+
+    match query {
+        DeleteObjectQuery::AbortMultipartUpload { upload_id } => {
+            actions::abort_multipart_upload(pool, drivers, bucket, key, upload_id).await
+        }
+        DeleteObjectQuery::DeleteObject { version_id } => {
+            actions::delete_object(pool, drivers, bucket, key, version_id).await
+        }
+    }
 }
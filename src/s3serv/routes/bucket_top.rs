@@ -1,18 +1,25 @@
+use std::sync::Arc;
+
 use axum::{
     extract::{Path, Query, State},
     response::{IntoResponse, Response},
 };
 use sqlx::PgPool;
 
-use crate::s3serv::{actions, error::S3Error};
+use crate::{
+    drivers::Drivers,
+    s3serv::{actions, error::S3Error, AppState},
+};
 
 #[derive(serde::Deserialize)]
 #[serde(untagged)]
 pub enum PutBucketQuery {
     PutBucketVersioning {
+        #[allow(dead_code)]
         versioning: String,
     },
     ObjectLock {
+        #[allow(dead_code)]
         #[serde(rename = "object-lock")]
         object_lock: String,
     },
@@ -23,10 +30,11 @@ async fn put_bucket_top(
     Path(bucket): Path<String>,
     State(pool): State<PgPool>,
     Query(query): Query<PutBucketQuery>,
+    body: String,
 ) -> Response {
     match query {
         PutBucketQuery::PutBucketVersioning { versioning: _ } => {
-            S3Error::NotImplemented.into_response()
+            actions::put_bucket_versioning(pool, bucket, body).await
         }
         PutBucketQuery::ObjectLock { object_lock: _ } => S3Error::NotImplemented.into_response(),
         PutBucketQuery::CreateBucket {} => actions::create_bucket(pool, bucket).await,
@@ -36,7 +44,48 @@ async fn put_bucket_top(
 #[derive(serde::Deserialize)]
 #[serde(untagged)]
 pub enum GetBucketTopQuery {
-    ListObjects { prefix: Option<String> },
+    GetBucketVersioning {
+        #[allow(dead_code)]
+        versioning: String,
+    },
+    ListMultipartUploads {
+        #[allow(dead_code)]
+        uploads: String,
+        prefix: Option<String>,
+        #[serde(rename = "key-marker")]
+        key_marker: Option<String>,
+        #[serde(rename = "upload-id-marker")]
+        upload_id_marker: Option<String>,
+        #[serde(rename = "max-uploads")]
+        max_uploads: Option<String>,
+    },
+    ListObjectVersions {
+        #[allow(dead_code)]
+        versions: String,
+        prefix: Option<String>,
+        delimiter: Option<String>,
+        #[serde(rename = "key-marker")]
+        key_marker: Option<String>,
+        #[serde(rename = "version-id-marker")]
+        version_id_marker: Option<String>,
+        #[serde(rename = "max-keys")]
+        max_keys: Option<String>,
+    },
+    ListObjects {
+        #[serde(rename = "list-type")]
+        list_type: Option<String>,
+        prefix: Option<String>,
+        delimiter: Option<String>,
+        #[serde(rename = "max-keys")]
+        max_keys: Option<String>,
+        #[serde(rename = "encoding-type")]
+        encoding_type: Option<String>,
+        marker: Option<String>,
+        #[serde(rename = "continuation-token")]
+        continuation_token: Option<String>,
+        #[serde(rename = "start-after")]
+        start_after: Option<String>,
+    },
 }
 
 async fn get_bucket_top(
@@ -45,8 +94,89 @@ async fn get_bucket_top(
     Query(query): Query<GetBucketTopQuery>,
 ) -> Response {
     match query {
-        GetBucketTopQuery::ListObjects { prefix } => {
-            actions::list_objects(pool, bucket, prefix.unwrap_or_default()).await
+        GetBucketTopQuery::GetBucketVersioning { versioning: _ } => {
+            actions::get_bucket_versioning(pool, bucket).await
+        }
+        GetBucketTopQuery::ListMultipartUploads {
+            uploads: _,
+            prefix,
+            key_marker,
+            upload_id_marker,
+            max_uploads,
+        } => {
+            actions::list_multipart_uploads(
+                pool,
+                bucket,
+                prefix.unwrap_or_default(),
+                key_marker.unwrap_or_default(),
+                upload_id_marker.unwrap_or_default(),
+                max_uploads,
+            )
+            .await
+        }
+        GetBucketTopQuery::ListObjectVersions {
+            versions: _,
+            prefix,
+            delimiter,
+            key_marker,
+            version_id_marker,
+            max_keys,
+        } => {
+            actions::list_object_versions(
+                pool,
+                bucket,
+                prefix.unwrap_or_default(),
+                delimiter.unwrap_or_default(),
+                key_marker.unwrap_or_default(),
+                version_id_marker.unwrap_or_default(),
+                max_keys,
+            )
+            .await
+        }
+        GetBucketTopQuery::ListObjects {
+            list_type,
+            prefix,
+            delimiter,
+            max_keys,
+            encoding_type,
+            marker,
+            continuation_token,
+            start_after,
+        } => {
+            let options = actions::ListObjectsOptions {
+                list_type,
+                prefix: prefix.unwrap_or_default(),
+                delimiter: delimiter.unwrap_or_default(),
+                max_keys,
+                encoding_type,
+                marker,
+                continuation_token,
+                start_after,
+            };
+            actions::list_objects(pool, bucket, options).await
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum PostBucketTopQuery {
+    DeleteObjects {
+        #[allow(dead_code)]
+        delete: String,
+    },
+}
+
+async fn post_bucket_top(
+    Path(bucket): Path<String>,
+    State(pool): State<PgPool>,
+    State(drivers): State<Arc<Drivers>>,
+    Query(query): Query<PostBucketTopQuery>,
+    body: String,
+) -> Response {
+    match query {
+        PostBucketTopQuery::DeleteObjects { delete: _ } => {
+            actions::delete_objects(pool, drivers, bucket, body).await
         }
     }
 }
@@ -55,8 +185,9 @@ async fn delete_bucket_top(Path(bucket): Path<String>, State(pool): State<PgPool
     actions::delete_bucket(pool, bucket).await
 }
 
-pub fn bucket_top() -> axum::routing::MethodRouter<PgPool> {
+pub fn bucket_top() -> axum::routing::MethodRouter<AppState> {
     axum::routing::put(put_bucket_top)
         .get(get_bucket_top)
+        .post(post_bucket_top)
         .delete(delete_bucket_top)
 }
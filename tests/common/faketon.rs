@@ -0,0 +1,174 @@
+//! An in-process stand-in for the ton HTTP API, keeping everything in memory.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use md5::Digest;
+
+#[derive(Default)]
+struct FakeTon {
+    chunk_size: usize,
+    /// Whether every third request fails.
+    flaky: bool,
+    requests: usize,
+    /// token -> chunks by offset
+    sessions: HashMap<String, BTreeMap<u64, Bytes>>,
+    /// ref -> chunks by offset
+    files: HashMap<String, BTreeMap<u64, Bytes>>,
+}
+
+impl FakeTon {
+    fn should_fail(&mut self) -> bool {
+        self.requests += 1;
+        self.flaky && self.requests.is_multiple_of(3)
+    }
+}
+
+type SharedTon = Arc<Mutex<FakeTon>>;
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ChunkQuery {
+    token: String,
+    offset: u64,
+}
+
+#[derive(serde::Serialize)]
+struct StartResponse {
+    token: String,
+    chunk_size: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct FinalizeRequest {
+    md5: String,
+}
+
+#[derive(serde::Serialize)]
+struct FinalizeResponse {
+    r#ref: String,
+}
+
+async fn start_upload(State(ton): State<SharedTon>) -> Response {
+    let mut ton = ton.lock().unwrap();
+    if ton.should_fail() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    ton.sessions.insert(token.clone(), BTreeMap::new());
+    Json(StartResponse {
+        token,
+        chunk_size: ton.chunk_size,
+    })
+    .into_response()
+}
+
+async fn upload_chunk(
+    State(ton): State<SharedTon>,
+    Query(query): Query<ChunkQuery>,
+    body: Bytes,
+) -> Response {
+    let mut ton = ton.lock().unwrap();
+    if ton.should_fail() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    if body.len() > ton.chunk_size {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    match ton.sessions.get_mut(&query.token) {
+        Some(chunks) => {
+            chunks.insert(query.offset, body);
+            StatusCode::OK.into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn finalize_upload(
+    State(ton): State<SharedTon>,
+    Query(query): Query<TokenQuery>,
+    Json(request): Json<FinalizeRequest>,
+) -> Response {
+    let mut ton = ton.lock().unwrap();
+    if ton.should_fail() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    let Some(chunks) = ton.sessions.remove(&query.token) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut md5 = md5::Md5::new();
+    let mut expected_offset = 0;
+    for (offset, chunk) in &chunks {
+        if *offset != expected_offset {
+            return (StatusCode::BAD_REQUEST, "missing chunk").into_response();
+        }
+        md5.update(chunk);
+        expected_offset += chunk.len() as u64;
+    }
+    if hex::encode(md5.finalize()) != request.md5 {
+        return (StatusCode::BAD_REQUEST, "md5 mismatch").into_response();
+    }
+
+    let r#ref = uuid::Uuid::new_v4().simple().to_string();
+    ton.files.insert(r#ref.clone(), chunks);
+    Json(FinalizeResponse { r#ref }).into_response()
+}
+
+async fn get_chunk(
+    State(ton): State<SharedTon>,
+    Path((r#ref, offset)): Path<(String, u64)>,
+) -> Response {
+    let mut ton = ton.lock().unwrap();
+    let fail = ton.should_fail();
+    let Some(chunk) = ton.files.get(&r#ref).and_then(|v| v.get(&offset)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !fail {
+        return chunk.clone().into_response();
+    }
+    // the connection breaks halfway through the body
+    let half = chunk.slice(..chunk.len() / 2);
+    Body::from_stream(tokio_stream::iter([
+        Ok(half),
+        Err(std::io::Error::other("connection lost")),
+    ]))
+    .into_response()
+}
+
+/// Serves a fresh fake ton on a random port, returning its base URL. A `flaky` one
+/// fails every third request, reads by breaking off halfway through the chunk.
+pub async fn start(chunk_size: usize, flaky: bool) -> String {
+    let ton = Arc::new(Mutex::new(FakeTon {
+        chunk_size,
+        flaky,
+        ..Default::default()
+    }));
+
+    let app = Router::new()
+        .route("/", get(|| async { StatusCode::OK }))
+        .route("/v1/upload/start", post(start_upload))
+        .route("/v1/upload/chunk", post(upload_chunk))
+        .route("/v1/upload/finalize", post(finalize_upload))
+        .route("/v1/files/{ref}/chunks/{offset}", get(get_chunk))
+        .with_state(ton);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
@@ -0,0 +1,133 @@
+//! Runs sagisawa in-process against the throwaway database of a `#[sqlx::test]`.
+
+use std::{sync::Arc, time::Duration};
+
+use aws_sdk_s3::config::{Credentials, Region};
+use sagisawa::drivers::{
+    fs::FsDriver,
+    memory::MemoryDriver,
+    s3::{S3Config, S3Driver},
+    ton::{RetryPolicy, TonConfig, TonDriver},
+    Drivers, StorageDriver,
+};
+use sqlx::PgPool;
+
+pub mod faketon;
+
+const ACCESS_KEY_ID: &str = "test";
+const SECRET_ACCESS_KEY: &str = "testsecret";
+
+/// Serves sagisawa on a random port and returns its endpoint.
+async fn serve(pool: PgPool, driver: Arc<dyn StorageDriver>) -> String {
+    let app = sagisawa::s3serv::router(pool, Drivers::new(driver));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn client(endpoint: &str) -> aws_sdk_s3::Client {
+    let config = aws_sdk_s3::Config::builder()
+        .endpoint_url(endpoint)
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new(
+            ACCESS_KEY_ID,
+            SECRET_ACCESS_KEY,
+            None,
+            None,
+            "test",
+        ))
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+async fn insert_access_key(pool: &PgPool) {
+    sqlx::query("INSERT INTO access_keys(access_key_id, secret_access_key) VALUES ($1, $2)")
+        .bind(ACCESS_KEY_ID)
+        .bind(SECRET_ACCESS_KEY)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// Serves sagisawa on a random port and returns a client for it.
+pub async fn start(pool: PgPool, driver: Arc<dyn StorageDriver>) -> aws_sdk_s3::Client {
+    insert_access_key(&pool).await;
+    client(&serve(pool, driver).await)
+}
+
+/// sagisawa storing into memory, small chunks so that objects span several of them.
+pub async fn start_memory(pool: PgPool) -> (aws_sdk_s3::Client, Arc<MemoryDriver>) {
+    let driver = Arc::new(MemoryDriver::new(64 * 1024));
+    (start(pool, driver.clone()).await, driver)
+}
+
+/// sagisawa storing into a temporary directory.
+pub async fn start_fs(pool: PgPool) -> (aws_sdk_s3::Client, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let client = start(pool, Arc::new(FsDriver::new(dir.path().into()))).await;
+    (client, dir)
+}
+
+/// sagisawa storing into a fake ton, after trying `dead_servers` servers which don't
+/// answer first.
+pub async fn start_ton(
+    pool: PgPool,
+    chunk_size: usize,
+    dead_servers: usize,
+    flaky: bool,
+) -> aws_sdk_s3::Client {
+    let mut base_urls = Vec::new();
+    for _ in 0..dead_servers {
+        // nothing listens there once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        base_urls.push(format!("http://{}", listener.local_addr().unwrap()));
+    }
+    base_urls.push(faketon::start(chunk_size, flaky).await);
+    let config = TonConfig {
+        base_urls,
+        retry: RetryPolicy {
+            retries: 4,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(100),
+        },
+        ..Default::default()
+    };
+    start(pool, Arc::new(TonDriver::new(config))).await
+}
+
+/// sagisawa storing into the bucket `upstream` of a second sagisawa, which stores into
+/// the returned directory. Both share the database and the access key.
+pub async fn start_s3_gateway(pool: PgPool) -> (aws_sdk_s3::Client, tempfile::TempDir) {
+    insert_access_key(&pool).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let upstream = serve(pool.clone(), Arc::new(FsDriver::new(dir.path().into()))).await;
+    client(&upstream)
+        .create_bucket()
+        .bucket("upstream")
+        .send()
+        .await
+        .unwrap();
+
+    let config = S3Config {
+        endpoint: upstream.parse().unwrap(),
+        bucket: "upstream".to_string(),
+        region: "us-east-1".to_string(),
+        access_key_id: ACCESS_KEY_ID.to_string(),
+        secret_access_key: SECRET_ACCESS_KEY.to_string(),
+        prefix: "data/".to_string(),
+        connect_timeout: Duration::from_secs(5),
+        read_timeout: Duration::from_secs(60),
+    };
+    let gateway = serve(pool, Arc::new(S3Driver::new(config))).await;
+    (client(&gateway), dir)
+}
+
+/// Some bytes which differ at every offset for a long while.
+pub fn test_data(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i % 251) as u8 ^ (i / 251) as u8)
+        .collect()
+}
@@ -0,0 +1,738 @@
+use std::{path::Path, time::Duration};
+
+use aws_sdk_s3::{
+    error::ProvideErrorMetadata,
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{
+        BucketVersioningStatus, ChecksumAlgorithm, ChecksumMode, CompletedMultipartUpload,
+        CompletedPart, VersioningConfiguration,
+    },
+};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use md5::Digest;
+use sagisawa::drivers::s3::PART_SIZE;
+use sqlx::PgPool;
+
+mod common;
+
+async fn get_bytes(client: &aws_sdk_s3::Client, key: &str, range: Option<&str>) -> Vec<u8> {
+    let res = client
+        .get_object()
+        .bucket("test")
+        .key(key)
+        .set_range(range.map(str::to_string))
+        .send()
+        .await
+        .unwrap();
+    res.body.collect().await.unwrap().into_bytes().to_vec()
+}
+
+async fn put_bytes(client: &aws_sdk_s3::Client, key: &str, data: &[u8]) {
+    client
+        .put_object()
+        .bucket("test")
+        .key(key)
+        .body(ByteStream::from(data.to_vec()))
+        .send()
+        .await
+        .unwrap();
+}
+
+/// Counts the files below `dir`, leaving out the directories themselves.
+fn count_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                count_files(&entry.path())
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+async fn create_bucket(client: &aws_sdk_s3::Client) {
+    client.create_bucket().bucket("test").send().await.unwrap();
+}
+
+#[sqlx::test]
+async fn put_and_get_from_memory(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let data = common::test_data(300_000);
+    put_bytes(&client, "dir/object", &data).await;
+    assert_eq!(driver.object_count(), 1);
+
+    assert_eq!(get_bytes(&client, "dir/object", None).await, data);
+    assert_eq!(
+        get_bytes(&client, "dir/object", Some("bytes=1000-70000")).await,
+        &data[1000..=70000]
+    );
+    assert_eq!(
+        get_bytes(&client, "dir/object", Some("bytes=-10")).await,
+        &data[data.len() - 10..]
+    );
+
+    let head = client
+        .head_object()
+        .bucket("test")
+        .key("dir/object")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(head.content_length(), Some(data.len() as i64));
+    assert_eq!(
+        head.e_tag(),
+        Some(format!("\"{}\"", hex::encode(md5::Md5::digest(&data))).as_str())
+    );
+}
+
+#[sqlx::test]
+async fn put_and_get_from_ton(pool: PgPool) {
+    let client = common::start_ton(pool, 64 * 1024, 0, false).await;
+    create_bucket(&client).await;
+
+    let data = common::test_data(300_000);
+    put_bytes(&client, "object", &data).await;
+
+    assert_eq!(get_bytes(&client, "object", None).await, data);
+    // starts and ends in the middle of chunks
+    assert_eq!(
+        get_bytes(&client, "object", Some("bytes=70000-200000")).await,
+        &data[70000..=200000]
+    );
+}
+
+#[sqlx::test]
+async fn ton_fails_over_to_a_working_server(pool: PgPool) {
+    let client = common::start_ton(pool, 64 * 1024, 2, false).await;
+    create_bucket(&client).await;
+
+    // every request starts at another server, some of them at dead ones
+    let data = common::test_data(200_000);
+    for key in ["a", "b", "c"] {
+        put_bytes(&client, key, &data).await;
+    }
+    for key in ["a", "b", "c"] {
+        assert_eq!(get_bytes(&client, key, None).await, data);
+    }
+}
+
+#[sqlx::test]
+async fn ton_retries_failed_requests(pool: PgPool) {
+    let client = common::start_ton(pool, 64 * 1024, 0, true).await;
+    create_bucket(&client).await;
+
+    // uploads and reads of every chunk fail now and then, some reads halfway
+    let data = common::test_data(500_000);
+    put_bytes(&client, "object", &data).await;
+    assert_eq!(get_bytes(&client, "object", None).await, data);
+    assert_eq!(
+        get_bytes(&client, "object", Some("bytes=100000-400000")).await,
+        &data[100000..=400000]
+    );
+    client
+        .delete_object()
+        .bucket("test")
+        .key("object")
+        .send()
+        .await
+        .unwrap();
+}
+
+#[sqlx::test]
+async fn fs_deletes_unreferenced_files(pool: PgPool) {
+    let (client, dir) = common::start_fs(pool).await;
+    create_bucket(&client).await;
+    let objects = dir.path().join("objects");
+
+    put_bytes(&client, "object", b"first").await;
+    put_bytes(&client, "object", b"second").await;
+    put_bytes(&client, "other", b"other").await;
+    assert_eq!(count_files(&objects), 2);
+    assert_eq!(get_bytes(&client, "object", None).await, b"second");
+
+    for key in ["object", "other"] {
+        client
+            .delete_object()
+            .bucket("test")
+            .key(key)
+            .send()
+            .await
+            .unwrap();
+    }
+    assert_eq!(count_files(&objects), 0);
+}
+
+#[sqlx::test]
+async fn s3_gateway_to_fs(pool: PgPool) {
+    let (client, dir) = common::start_s3_gateway(pool).await;
+    create_bucket(&client).await;
+
+    let small = common::test_data(1000);
+    put_bytes(&client, "small", &small).await;
+    assert_eq!(get_bytes(&client, "small", None).await, small);
+
+    // uploaded to the upstream with multipart
+    let large = common::test_data(2 * PART_SIZE + 1000);
+    put_bytes(&client, "large", &large).await;
+    assert_eq!(get_bytes(&client, "large", None).await, large);
+    assert_eq!(
+        get_bytes(&client, "large", Some("bytes=8000000-9000000")).await,
+        &large[8000000..=9000000]
+    );
+
+    let objects = dir.path().join("objects");
+    assert!(count_files(&objects) > 0);
+
+    // deleted from the upstream, which in turn deletes its files
+    for key in ["small", "large"] {
+        client
+            .delete_object()
+            .bucket("test")
+            .key(key)
+            .send()
+            .await
+            .unwrap();
+    }
+    assert_eq!(count_files(&objects), 0);
+}
+
+#[sqlx::test]
+async fn empty_object(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    put_bytes(&client, "empty", b"").await;
+    assert_eq!(driver.object_count(), 0);
+    assert_eq!(get_bytes(&client, "empty", None).await, b"");
+
+    // the checksum is kept although there is no data
+    client
+        .put_object()
+        .bucket("test")
+        .key("empty")
+        .checksum_algorithm(ChecksumAlgorithm::Sha256)
+        .body(ByteStream::from_static(b""))
+        .send()
+        .await
+        .unwrap();
+    let head = client
+        .head_object()
+        .bucket("test")
+        .key("empty")
+        .checksum_mode(ChecksumMode::Enabled)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        head.checksum_sha256(),
+        Some(BASE64_STANDARD.encode(sha2::Sha256::digest(b"")).as_str())
+    );
+    assert_eq!(driver.object_count(), 0);
+}
+
+#[sqlx::test]
+async fn bad_content_md5_stores_nothing(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let err = client
+        .put_object()
+        .bucket("test")
+        .key("object")
+        .content_md5("1B2M2Y8AsgTpgAmY7PhCfg==")
+        .body(ByteStream::from_static(b"not empty"))
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("BadDigest"));
+    assert_eq!(driver.object_count(), 0);
+
+    let err = client
+        .get_object()
+        .bucket("test")
+        .key("object")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("NoSuchKey"));
+}
+
+#[sqlx::test]
+async fn multipart_upload(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let data = common::test_data(5 * 1024 * 1024 + 1000);
+    let (first, second) = data.split_at(5 * 1024 * 1024);
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket("test")
+        .key("multipart")
+        .send()
+        .await
+        .unwrap();
+    let upload_id = upload.upload_id().unwrap();
+
+    let mut parts = Vec::new();
+    for (i, part) in [first, second].into_iter().enumerate() {
+        let part_number = i as i32 + 1;
+        let res = client
+            .upload_part()
+            .bucket("test")
+            .key("multipart")
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(part.to_vec()))
+            .send()
+            .await
+            .unwrap();
+        parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .e_tag(res.e_tag().unwrap())
+                .build(),
+        );
+    }
+
+    let res = client
+        .complete_multipart_upload()
+        .bucket("test")
+        .key("multipart")
+        .upload_id(upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert!(res.e_tag().unwrap().ends_with("-2\""));
+
+    assert_eq!(get_bytes(&client, "multipart", None).await, data);
+    assert_eq!(
+        get_bytes(&client, "multipart", Some("bytes=5242000-5242999")).await,
+        &data[5242000..=5242999]
+    );
+}
+
+#[sqlx::test]
+async fn versioning_keeps_old_versions(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+    client
+        .put_bucket_versioning()
+        .bucket("test")
+        .versioning_configuration(
+            VersioningConfiguration::builder()
+                .status(BucketVersioningStatus::Enabled)
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    put_bytes(&client, "object", b"first").await;
+    put_bytes(&client, "object", b"second").await;
+
+    let versions = client
+        .list_object_versions()
+        .bucket("test")
+        .send()
+        .await
+        .unwrap();
+    let versions = versions.versions();
+    assert_eq!(versions.len(), 2);
+    let old = versions
+        .iter()
+        .find(|v| v.is_latest() == Some(false))
+        .unwrap();
+
+    let res = client
+        .get_object()
+        .bucket("test")
+        .key("object")
+        .version_id(old.version_id().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        res.body.collect().await.unwrap().into_bytes(),
+        &b"first"[..]
+    );
+    assert_eq!(get_bytes(&client, "object", None).await, b"second");
+}
+
+#[sqlx::test]
+async fn copy_object_shares_data(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    put_bytes(&client, "source", b"copied").await;
+    client
+        .copy_object()
+        .bucket("test")
+        .key("destination")
+        .copy_source("test/source")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(driver.object_count(), 1);
+    assert_eq!(get_bytes(&client, "destination", None).await, b"copied");
+}
+
+#[sqlx::test]
+async fn unreferenced_data_is_deleted(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    put_bytes(&client, "object", b"first").await;
+    put_bytes(&client, "object", b"second").await;
+    assert_eq!(driver.object_count(), 1);
+    assert_eq!(get_bytes(&client, "object", None).await, b"second");
+
+    // shared data stays until its last reference is gone
+    client
+        .copy_object()
+        .bucket("test")
+        .key("copy")
+        .copy_source("test/object")
+        .send()
+        .await
+        .unwrap();
+    for (key, count) in [("object", 1), ("copy", 0)] {
+        client
+            .delete_object()
+            .bucket("test")
+            .key(key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(driver.object_count(), count);
+    }
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket("test")
+        .key("multipart")
+        .send()
+        .await
+        .unwrap();
+    let upload_id = upload.upload_id().unwrap();
+    for data in [&b"replaced"[..], b"part"] {
+        client
+            .upload_part()
+            .bucket("test")
+            .key("multipart")
+            .upload_id(upload_id)
+            .part_number(1)
+            .body(ByteStream::from(data.to_vec()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(driver.object_count(), 1);
+    }
+    client
+        .abort_multipart_upload()
+        .bucket("test")
+        .key("multipart")
+        .upload_id(upload_id)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(driver.object_count(), 0);
+
+    client
+        .put_bucket_versioning()
+        .bucket("test")
+        .versioning_configuration(
+            VersioningConfiguration::builder()
+                .status(BucketVersioningStatus::Enabled)
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+    put_bytes(&client, "versioned", b"version").await;
+    let versions = client
+        .list_object_versions()
+        .bucket("test")
+        .send()
+        .await
+        .unwrap();
+    client
+        .delete_object()
+        .bucket("test")
+        .key("versioned")
+        .version_id(versions.versions()[0].version_id().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(driver.object_count(), 0);
+}
+
+#[sqlx::test]
+async fn conditional_get(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+    put_bytes(&client, "object", b"hello").await;
+
+    let etag = format!("\"{}\"", hex::encode(md5::Md5::digest(b"hello")));
+    let err = client
+        .get_object()
+        .bucket("test")
+        .key("object")
+        .if_none_match(&etag)
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.raw_response().unwrap().status().as_u16(), 304);
+
+    let err = client
+        .get_object()
+        .bucket("test")
+        .key("object")
+        .if_match("\"other\"")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.raw_response().unwrap().status().as_u16(), 412);
+}
+
+#[sqlx::test]
+async fn list_and_delete(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+    for key in ["a/1", "a/2", "b", "c/d/e"] {
+        put_bytes(&client, key, key.as_bytes()).await;
+    }
+
+    let res = client
+        .list_objects_v2()
+        .bucket("test")
+        .delimiter("/")
+        .send()
+        .await
+        .unwrap();
+    let keys = res
+        .contents()
+        .iter()
+        .filter_map(|v| v.key())
+        .collect::<Vec<_>>();
+    let prefixes = res
+        .common_prefixes()
+        .iter()
+        .filter_map(|v| v.prefix())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["b"]);
+    assert_eq!(prefixes, ["a/", "c/"]);
+
+    // nothing to page through, clients looping until not truncated stop
+    let res = client
+        .list_objects_v2()
+        .bucket("test")
+        .max_keys(0)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.contents().is_empty());
+    assert_eq!(res.is_truncated(), Some(false));
+    let res = client
+        .list_object_versions()
+        .bucket("test")
+        .max_keys(0)
+        .send()
+        .await
+        .unwrap();
+    assert!(res.versions().is_empty());
+    assert_eq!(res.is_truncated(), Some(false));
+
+    client
+        .delete_object()
+        .bucket("test")
+        .key("a/1")
+        .send()
+        .await
+        .unwrap();
+    let res = client
+        .list_objects_v2()
+        .bucket("test")
+        .prefix("a/")
+        .send()
+        .await
+        .unwrap();
+    let keys = res
+        .contents()
+        .iter()
+        .filter_map(|v| v.key())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["a/2"]);
+}
+
+#[sqlx::test]
+async fn presigned_put_refuses_unsigned_amz_headers(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+    put_bytes(&client, "secret", b"secret").await;
+
+    let presigned = client
+        .put_object()
+        .bucket("test")
+        .key("target")
+        .presigned(PresigningConfig::expires_in(Duration::from_secs(60)).unwrap())
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    let res = http
+        .put(presigned.uri())
+        .header("x-amz-copy-source", "test/secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+    assert!(res.text().await.unwrap().contains("not signed"));
+    let err = client
+        .get_object()
+        .bucket("test")
+        .key("target")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("NoSuchKey"));
+
+    let res = http
+        .put(presigned.uri())
+        .body("uploaded")
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(get_bytes(&client, "target", None).await, b"uploaded");
+}
+
+#[sqlx::test]
+async fn trailer_needs_a_chunked_body(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let err = client
+        .put_object()
+        .bucket("test")
+        .key("object")
+        .body(ByteStream::from_static(b"no trailer follows"))
+        .customize()
+        .mutate_request(|req| {
+            let headers = req.headers_mut();
+            headers.remove("x-amz-checksum-crc32");
+            headers.remove("x-amz-sdk-checksum-algorithm");
+            headers.insert("x-amz-trailer", "x-amz-checksum-crc32");
+        })
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("InvalidArgument"));
+    assert_eq!(driver.object_count(), 0);
+}
+
+#[sqlx::test]
+async fn put_object_returns_etag_for_compare_and_swap(pool: PgPool) {
+    let (client, _) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let first = client
+        .put_object()
+        .bucket("test")
+        .key("object")
+        .body(ByteStream::from_static(b"first"))
+        .send()
+        .await
+        .unwrap();
+    let first_etag = first.e_tag().unwrap().to_string();
+    assert_eq!(
+        first_etag,
+        format!("\"{}\"", hex::encode(md5::Md5::digest(b"first")))
+    );
+
+    let second = client
+        .put_object()
+        .bucket("test")
+        .key("object")
+        .if_match(&first_etag)
+        .body(ByteStream::from_static(b"second"))
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(second.e_tag(), Some(first_etag.as_str()));
+
+    let err = client
+        .put_object()
+        .bucket("test")
+        .key("object")
+        .if_match(&first_etag)
+        .body(ByteStream::from_static(b"third"))
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.raw_response().unwrap().status().as_u16(), 412);
+    assert_eq!(get_bytes(&client, "object", None).await, b"second");
+}
+
+#[sqlx::test]
+async fn upload_part_checks_digests(pool: PgPool) {
+    let (client, driver) = common::start_memory(pool).await;
+    create_bucket(&client).await;
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket("test")
+        .key("multipart")
+        .send()
+        .await
+        .unwrap();
+    let upload_part = || {
+        client
+            .upload_part()
+            .bucket("test")
+            .key("multipart")
+            .upload_id(upload.upload_id().unwrap())
+            .part_number(1)
+            .body(ByteStream::from_static(b"part"))
+    };
+
+    let err = upload_part()
+        .content_md5("1B2M2Y8AsgTpgAmY7PhCfg==")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("BadDigest"));
+    let err = upload_part()
+        .checksum_crc32("AAAAAA==")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("BadDigest"));
+    assert_eq!(driver.object_count(), 0);
+
+    let res = upload_part()
+        .checksum_algorithm(ChecksumAlgorithm::Sha256)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        res.checksum_sha256(),
+        Some(
+            BASE64_STANDARD
+                .encode(sha2::Sha256::digest(b"part"))
+                .as_str()
+        )
+    );
+}